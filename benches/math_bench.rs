@@ -0,0 +1,22 @@
+use braillegl::{canvas::Canvas, entity::Entity, math::{Mat4x4f, Vec3f}, scene::Scene, shapes};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn math_benchmark(c: &mut Criterion) {
+    let a = Mat4x4f::rotate_y(0.3) * Mat4x4f::translation(1.0, 2.0, 3.0);
+    let b = Mat4x4f::projection(16.0 / 9.0, 90.0, 0.1, 1000.0);
+    let v = Vec3f::new(1.0, 2.0, 3.0);
+    c.bench_function("mat4x4f mul", |bench| bench.iter(|| black_box(a) * black_box(b)));
+    c.bench_function("mat4x4f vecmul", |bench| bench.iter(|| black_box(a).vecmul(black_box(&v), true)));
+}
+
+fn render_benchmark(c: &mut Criterion) {
+    let mut scene = Scene::new();
+    let mut sphere = Entity::with_geometry(shapes::make_icosphere(1.0, 3));
+    sphere.set_translation(0.0, 0.0, -3.0);
+    scene.add(sphere);
+    let mut canvas = Canvas::with_size(160, 160);
+    c.bench_function("render icosphere", |bench| bench.iter(|| canvas.render_scene_once(&scene)));
+}
+
+criterion_group!(benches, math_benchmark, render_benchmark);
+criterion_main!(benches);
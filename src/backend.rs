@@ -0,0 +1,243 @@
+#[cfg(feature = "termion")]
+use std::fmt::Write;
+
+use crate::{input::Key, texture::Color};
+
+#[cfg(not(any(feature = "termion", feature = "crossterm")))]
+compile_error!("braillegl needs a terminal backend, enable either the `termion` or the `crossterm` feature");
+
+/// The backend used by `Canvas` and `Window`. If both backends are
+/// enabled `termion` is preferred.
+#[cfg(feature = "termion")]
+pub type DefaultBackend = TermionBackend;
+#[cfg(all(feature = "crossterm", not(feature = "termion")))]
+pub type DefaultBackend = CrosstermBackend;
+
+/// The terminal operations needed to display a canvas. Output is
+/// written as escape sequences into a string so that a whole frame
+/// can be flushed at once.
+pub trait Backend {
+    /// Keeps the terminal in raw mode until dropped
+    type RawMode;
+
+    /// Puts the terminal into raw mode. The terminal is restored when
+    /// the returned guard is dropped.
+    fn raw_mode() -> Self::RawMode;
+
+    /// Source of key presses read from the terminal
+    type Input;
+
+    /// Starts reading key presses from the terminal
+    fn input() -> Self::Input;
+
+    /// Returns the keys pressed since the last poll, without blocking
+    fn poll_keys(input: &mut Self::Input) -> Vec<Key>;
+
+    /// Returns the size of the terminal as (columns, rows)
+    fn terminal_size() -> (u16, u16);
+
+    /// Clears the entire screen
+    fn clear(out: &mut String);
+
+    /// Moves the cursor to (`col`, `row`). Like `termion::cursor::Goto`
+    /// the coordinates are 1-based.
+    fn goto(out: &mut String, col: u16, row: u16);
+
+    /// Sets the foreground color of the following characters
+    fn set_fg(out: &mut String, color: Color);
+
+    /// Sets the foreground color of the following characters to entry
+    /// `index` of the terminal's 256-color palette. The first 16 entries
+    /// are the basic ANSI colors.
+    fn set_fg_indexed(out: &mut String, index: u8);
+
+    /// Sets the background color of the following characters, and of
+    /// the screen when cleared afterwards
+    fn set_bg(out: &mut String, color: Color);
+
+    /// Resets the foreground and background to the terminal's defaults
+    fn reset_colors(out: &mut String);
+
+    fn hide_cursor(out: &mut String);
+
+    fn show_cursor(out: &mut String);
+}
+
+#[cfg(feature = "termion")]
+pub struct TermionBackend;
+
+#[cfg(feature = "termion")]
+impl Backend for TermionBackend {
+    type RawMode = termion::raw::RawTerminal<std::io::Stdout>;
+
+    fn raw_mode() -> Self::RawMode {
+        use termion::raw::IntoRawMode;
+        std::io::stdout().into_raw_mode().unwrap()
+    }
+
+    type Input = termion::input::Keys<termion::AsyncReader>;
+
+    fn input() -> Self::Input {
+        use termion::input::TermRead;
+        termion::async_stdin().keys()
+    }
+
+    fn poll_keys(input: &mut Self::Input) -> Vec<Key> {
+        crate::input::read_termion_keys(input)
+    }
+
+    fn terminal_size() -> (u16, u16) {
+        termion::terminal_size().unwrap()
+    }
+
+    fn clear(out: &mut String) {
+        write!(out, "{}", termion::clear::All).unwrap();
+    }
+
+    fn goto(out: &mut String, col: u16, row: u16) {
+        write!(out, "{}", termion::cursor::Goto(col, row)).unwrap();
+    }
+
+    fn set_fg(out: &mut String, color: Color) {
+        out.push_str(&color.to_ansi_fg());
+    }
+
+    fn set_fg_indexed(out: &mut String, index: u8) {
+        use termion::color::AnsiValue;
+        write!(out, "{}", AnsiValue(index).fg_string()).unwrap();
+    }
+
+    fn set_bg(out: &mut String, color: Color) {
+        out.push_str(&color.to_ansi_bg());
+    }
+
+    fn reset_colors(out: &mut String) {
+        use termion::color::{Bg, Fg, Reset};
+        write!(out, "{}{}", Fg(Reset), Bg(Reset)).unwrap();
+    }
+
+    fn hide_cursor(out: &mut String) {
+        write!(out, "{}", termion::cursor::Hide).unwrap();
+    }
+
+    fn show_cursor(out: &mut String) {
+        write!(out, "{}", termion::cursor::Show).unwrap();
+    }
+}
+
+#[cfg(feature = "crossterm")]
+pub struct CrosstermBackend;
+
+/// Disables raw mode when dropped
+#[cfg(feature = "crossterm")]
+pub struct CrosstermRawMode;
+
+#[cfg(feature = "crossterm")]
+impl Drop for CrosstermRawMode {
+    fn drop(&mut self) {
+        crossterm::terminal::disable_raw_mode().unwrap();
+    }
+}
+
+#[cfg(feature = "crossterm")]
+impl Backend for CrosstermBackend {
+    type RawMode = CrosstermRawMode;
+
+    fn raw_mode() -> Self::RawMode {
+        crossterm::terminal::enable_raw_mode().unwrap();
+        CrosstermRawMode
+    }
+
+    type Input = ();
+
+    fn input() -> Self::Input {}
+
+    fn poll_keys(_input: &mut Self::Input) -> Vec<Key> {
+        use std::time::Duration;
+        use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+        let mut pressed = Vec::new();
+        while event::poll(Duration::from_secs(0)).unwrap() {
+            let key = match event::read().unwrap() {
+                Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                _ => continue,
+            };
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            pressed.push(match key.code {
+                KeyCode::Char(c) if ctrl => Key::Ctrl(c.to_ascii_lowercase()),
+                KeyCode::Char(c) => Key::Char(c.to_ascii_lowercase()),
+                KeyCode::Up => Key::Up,
+                KeyCode::Down => Key::Down,
+                KeyCode::Left => Key::Left,
+                KeyCode::Right => Key::Right,
+                KeyCode::Esc => Key::Esc,
+                _ => continue,
+            });
+        }
+        pressed
+    }
+
+    fn terminal_size() -> (u16, u16) {
+        crossterm::terminal::size().unwrap()
+    }
+
+    fn clear(out: &mut String) {
+        use crossterm::{Command, terminal::{Clear, ClearType}};
+        Clear(ClearType::All).write_ansi(out).unwrap();
+    }
+
+    fn goto(out: &mut String, col: u16, row: u16) {
+        use crossterm::{Command, cursor::MoveTo};
+        // crossterm is 0-based
+        MoveTo(col.saturating_sub(1), row.saturating_sub(1)).write_ansi(out).unwrap();
+    }
+
+    fn set_fg(out: &mut String, color: Color) {
+        use crossterm::{Command, style::{self, SetForegroundColor}};
+        let color = style::Color::Rgb { r: color.r, g: color.g, b: color.b };
+        SetForegroundColor(color).write_ansi(out).unwrap();
+    }
+
+    fn set_fg_indexed(out: &mut String, index: u8) {
+        use crossterm::{Command, style::{self, SetForegroundColor}};
+        SetForegroundColor(style::Color::AnsiValue(index)).write_ansi(out).unwrap();
+    }
+
+    fn set_bg(out: &mut String, color: Color) {
+        use crossterm::{Command, style::{self, SetBackgroundColor}};
+        let color = style::Color::Rgb { r: color.r, g: color.g, b: color.b };
+        SetBackgroundColor(color).write_ansi(out).unwrap();
+    }
+
+    fn reset_colors(out: &mut String) {
+        use crossterm::{Command, style::ResetColor};
+        ResetColor.write_ansi(out).unwrap();
+    }
+
+    fn hide_cursor(out: &mut String) {
+        use crossterm::{Command, cursor::Hide};
+        Hide.write_ansi(out).unwrap();
+    }
+
+    fn show_cursor(out: &mut String) {
+        use crossterm::{Command, cursor::Show};
+        Show.write_ansi(out).unwrap();
+    }
+}
+
+#[cfg(all(test, feature = "termion", feature = "crossterm"))]
+mod tests {
+    use super::*;
+    use crate::canvas::Canvas;
+
+    #[test]
+    fn backends_emit_the_same_frame() {
+        let mut canvas = Canvas::with_size(8, 8);
+        canvas.clear();
+        canvas.fill_triangle(0, 0, 7, 0, 0, 7, Color::RED, 0.0);
+        canvas.draw_line(0, 7, 7, 7, Color::BLUE, 0.0);
+
+        let termion = canvas.to_s_with::<TermionBackend>();
+        let crossterm = canvas.to_s_with::<CrosstermBackend>();
+        assert_eq!(termion, crossterm);
+    }
+}
@@ -1,94 +1,388 @@
-use std::{fmt::Write};
+use std::{f32::consts::PI, io};
 
-use termion::{clear, color::Rgb, cursor, terminal_size};
+use crate::{backend::{Backend, DefaultBackend}, entity::Entity, framebuffer::{AmbientOcclusion, FrameBuffer}, math::{Frustum, Mat4x4f, Vec3f}, scene::Scene, texture::{Color, ColorF, Texture}};
 
-use crate::{entity::Entity, math::{Mat4x4f, Vec3f}, texture::Color};
+/// How many times `fill_triangle_subdivided` halves a triangle at most
+const MAX_SUBDIVISION_LEVELS: u32 = 8;
 
 pub struct Canvas {
-    pixels: Vec<Option<Color>>,
-    width: usize,
-    height: usize,
-    win_x: i32,
-    win_y: i32,
-    pix_w: i32,
-    pix_h: i32,
+    frame: FrameBuffer,
+    cell_aspect: f32,
     projection_matrix: Mat4x4f,
     camera: Camera,
-    depth_buffer: Vec<f32>,
+    render_mode: RenderMode,
+    cull_mode: CullMode,
+    fog: Option<Fog>,
+    ambient_occlusion: Option<AmbientOcclusion>,
+    shade_fn: Option<Box<dyn Fn(ShadeInput) -> Color>>,
+    min_brightness: f32,
+    min_visible: Option<u8>,
+    antialias: bool,
+    subdivision_threshold: Option<u32>,
+    shading_mode: ShadingMode,
+    light_space: LightSpace,
+    fov: f32,
+    znear: f32,
+    zfar: f32,
+    stats: FrameStats,
 }
 
 impl Canvas {
-    pub fn pix2cell(&mut self, (x, y): (i32, i32)) -> (i32, i32) {
-        let x = (x - self.win_x) * self.width as i32 / self.pix_w;
-        let y = (y - self.win_y) * self.height as i32 / self.pix_h;
+    /// Maps a point in screen pixels to canvas pixels, given where the
+    /// terminal window is on screen as `(x, y, width, height)` in
+    /// screen pixels
+    pub fn pix2cell(&self, (x, y): (i32, i32), window: (i32, i32, i32, i32)) -> (i32, i32) {
+        let (win_x, win_y, pix_w, pix_h) = window;
+        let x = (x - win_x) * self.frame.width as i32 / pix_w;
+        let y = (y - win_y) * self.frame.height as i32 / pix_h;
         (x, y)
     }
 
     pub fn set(&mut self, x: i32, y: i32, color: Color, depth: f32) {
-        if !(x >= 0 && x < self.width as i32 && y >= 0 && y < self.height as i32) {
+        self.frame.set(x, y, color, depth);
+    }
+
+    /// Blends `color` over the pixel, giving `color * alpha + existing *
+    /// (1 - alpha)`. Pixels that haven't been drawn to are treated as
+    /// black. Depth is only written for opaque (`alpha >= 1`) colors, so
+    /// that surfaces behind transparent ones can still be drawn.
+    pub fn set_blended(&mut self, x: i32, y: i32, color: Color, alpha: f32, depth: f32) {
+        self.frame.set_blended(x, y, color, alpha, depth);
+    }
+
+    /// Like `set`, but the pixel is only partly covered. Coverage from
+    /// triangles sharing an edge adds up.
+    fn set_with_coverage(&mut self, x: i32, y: i32, color: Color, depth: f32, coverage: f32) {
+        self.frame.set_with_coverage(x, y, color, depth, coverage);
+    }
+
+    /// Enables estimating how much of each pixel filled triangles cover.
+    /// Pixels covered less than a quarter aren't shown, and
+    /// cell colors are dimmed by the coverage of their pixels, which
+    /// smooths out edges moving across cells.
+    pub fn set_antialias(&mut self, antialias: bool) {
+        self.antialias = antialias;
+    }
+
+    /// Splits filled triangles spanning more than `threshold` pixels
+    /// across or down into smaller ones before filling them, each with
+    /// its own depth. This hides seams between large neighboring
+    /// triangles and makes their depth more accurate. `None` disables it.
+    pub fn set_subdivision_threshold(&mut self, threshold: Option<u32>) {
+        self.subdivision_threshold = threshold.map(|t| t.max(2));
+    }
+
+    /// Restricts drawing to the rectangle (min x, min y, max x, max y),
+    /// both corners inclusive. `None` allows drawing anywhere.
+    pub fn set_scissor(&mut self, scissor: Option<(i32, i32, i32, i32)>) {
+        self.frame.set_scissor(scissor);
+    }
+
+    /// Sets the test a pixel's depth has to pass to be drawn. Depths are
+    /// in [-1, 1], where +1 is the near plane and -1 the far plane, so
+    /// nearer pixels have *larger* depths, see `normalize_depth`. The default,
+    /// `GreaterEqual`, thus keeps the nearest pixel.
+    pub fn set_depth_func(&mut self, depth_func: DepthFunc) {
+        self.frame.set_depth_func(depth_func);
+    }
+
+    pub fn get_depth_func(&self) -> DepthFunc {
+        self.frame.get_depth_func()
+    }
+
+    /// Sets the depth `clear` fills the depth buffer with. The default,
+    /// `f32::MIN`, is further away than anything, which suits the
+    /// default `DepthFunc::GreaterEqual`. Use `f32::MAX` with `Less`.
+    /// Takes effect on the next `clear`.
+    pub fn set_depth_clear_value(&mut self, depth: f32) {
+        self.frame.set_depth_clear_value(depth);
+    }
+
+    pub fn get_depth_clear_value(&self) -> f32 {
+        self.frame.get_depth_clear_value()
+    }
+
+    /// Sets whether pixels that pass the depth test update the depth
+    /// buffer. Disabling it is useful for transparent passes.
+    pub fn set_depth_write(&mut self, depth_write: bool) {
+        self.frame.set_depth_write(depth_write);
+    }
+
+    pub fn draw_entity(&mut self, e: &Entity) {
+        if e.is_billboard() {
+            let camera_position = self.camera.position;
+            self.draw_entity_transformed(e, 
+                e.gen_billboard_transform(camera_position), 
+                e.gen_billboard_normal_transform(camera_position),
+            );
+        } else {
+            self.draw_entity_transformed(e, e.gen_local_transform(), e.gen_normal_transform());
+        }
+    }
+
+    /// Sets the color of the terminal behind the canvas, or leaves the
+    /// terminal's own background if `None`
+    pub fn set_clear_color(&mut self, color: Option<Color>) {
+        self.frame.set_clear_color(color);
+    }
+
+    /// Sets the lowest brightness lit triangles are drawn with, so that
+    /// surfaces facing away from the light don't turn invisible. Defaults
+    /// to 0.1.
+    pub fn set_min_brightness(&mut self, min_brightness: f32) {
+        self.min_brightness = min_brightness.clamp(0.0, 1.0);
+    }
+
+    /// Raises the colors of entities that are lit but too dark to be
+    /// told apart from black to at least `min` in their brightest
+    /// channel, see `Color::clamp_to_visible`. `None` disables it.
+    pub fn set_min_visible(&mut self, min: Option<u8>) {
+        self.min_visible = min;
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) {
+        self.camera = camera;
+    }
+
+    pub fn get_camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// Rebuilds the projection with a vertical field of view of `fov`
+    /// degrees, clipping what's nearer than `near` or further than `far`
+    pub fn set_projection(&mut self, fov: f32, near: f32, far: f32) {
+        self.fov = fov;
+        self.znear = near;
+        self.zfar = far;
+        self.projection_matrix = Mat4x4f::projection_fov(
+            fov,
+            self.aspect_ratio(),
+            near, far
+        );
+        self.update_ambient_occlusion();
+    }
+
+    /// Sets the width of a terminal cell divided by its height. Most
+    /// fonts are about twice as tall as they are wide, which makes the
+    /// braille dots square at the default of 0.5.
+    pub fn set_cell_aspect(&mut self, cell_aspect: f32) {
+        self.cell_aspect = cell_aspect;
+        self.set_projection(self.fov, self.znear, self.zfar);
+    }
+
+    pub fn get_cell_aspect(&self) -> f32 {
+        self.cell_aspect
+    }
+
+    /// Returns the on-screen width of the canvas divided by its height.
+    /// A cell is 2 dots wide and 4 tall, so a dot is `2 * cell_aspect`
+    /// as wide as it is tall.
+    pub fn aspect_ratio(&self) -> f32 {
+        self.frame.width as f32 / self.frame.height as f32 * 2.0 * self.cell_aspect
+    }
+
+    /// Returns the vertical field of view in degrees
+    pub fn get_fov(&self) -> f32 {
+        self.fov
+    }
+
+    /// Changes the field of view, keeping the clipping planes
+    pub fn set_fov(&mut self, fov: f32) {
+        self.set_projection(fov, self.znear, self.zfar);
+    }
+
+    /// Returns the frustum of the camera, in world space
+    pub fn frustum(&self) -> Frustum {
+        (self.projection_matrix * self.camera.view_matrix()).frustum()
+    }
+
+    /// Returns false if the entity lies entirely outside the view, in
+    /// which case `draw_entity` won't draw any of its triangles
+    pub fn is_in_view(&self, e: &Entity) -> bool {
+        let (min, max) = e.world_bounding_box();
+        self.frustum().intersects_box(min, max)
+    }
+
+    /// Projects a point in view space onto the canvas, returning the
+    /// pixel coordinates as x and y and the depth as z
+    fn view_to_screen(&self, point: &Vec3f) -> Vec3f {
+        // Project into a 2x2x2 box, where all values are in the
+        // interval [-1, 1] with +Y up
+        let p = self.projection_matrix.vecmul(point, true);
+        Vec3f::new(
+            (p.x + 1.0) * self.frame.width as f32 / 2.0,
+            (1.0 - p.y) * self.frame.height as f32 / 2.0,
+            // The camera looks down -Z in view space
+            self.normalize_depth(-point.z),
+        )
+    }
+
+    /// Maps `distance` in front of the camera linearly onto the depths
+    /// stored in the depth buffer: +1 at the near plane and -1 at the
+    /// far plane. Unlike the projected z, which crowds most of the range
+    /// near the camera, equal distances give equal depth differences
+    /// across the whole frustum.
+    pub fn normalize_depth(&self, distance: f32) -> f32 {
+        1.0 - 2.0 * (distance - self.znear) / (self.zfar - self.znear)
+    }
+
+    /// Returns the distance in front of the camera a depth was stored
+    /// for, reversing `normalize_depth`
+    pub fn depth_to_distance(&self, depth: f32) -> f32 {
+        self.znear + (1.0 - depth) * (self.zfar - self.znear) / 2.0
+    }
+
+    /// Returns the pixel coordinates and depth that the point `world`
+    /// is drawn at, or `None` if it's behind the camera. The pixel may
+    /// be outside the canvas.
+    pub fn project_point(&self, world: Vec3f) -> Option<(i32, i32, f32)> {
+        let view = self.camera.view_matrix().vecmul(&world, true);
+        // The camera looks down -Z in view space
+        if view.z >= 0.0 {
+            return None;
+        }
+        let screen = self.view_to_screen(&view);
+        Some((screen.x.floor() as i32, screen.y.floor() as i32, screen.z))
+    }
+
+    /// Returns the point in world space drawn at the center of pixel
+    /// (`x`, `y`) with `depth`, reversing `project_point`
+    pub fn unproject(&self, x: i32, y: i32, depth: f32) -> Vec3f {
+        let m = self.projection_matrix.m;
+        let ndc_x = (x as f32 + 0.5) * 2.0 / self.frame.width as f32 - 1.0;
+        let ndc_y = 1.0 - (y as f32 + 0.5) * 2.0 / self.frame.height as f32;
+        // The projection divides x and y by w = -z
+        let z = -self.depth_to_distance(depth);
+        let view = Vec3f::new(ndc_x * -z / m[0][0], ndc_y * -z / m[1][1], z);
+
+        let camera = &self.camera;
+        camera.position
+            + camera.right().scale(view.x)
+            + camera.up().scale(view.y)
+            - camera.forward().scale(view.z)
+    }
+
+    /// Clears the canvas and draws the scene, without printing anything.
+    /// Together with `with_size` this renders the same frame every time,
+    /// which is useful for benchmarks and tests.
+    pub fn render_scene_once(&mut self, scene: &Scene) {
+        self.clear();
+        self.draw_scene(scene);
+    }
+
+    /// Draws every entity in the scene, placed by its world transform
+    pub fn draw_scene(&mut self, scene: &Scene) {
+        for (i, e) in scene.entities().enumerate() {
+            self.draw_entity_transformed(e, scene.world_transform(i), scene.world_normal_transform(i));
+        }
+    }
+
+    /// Draws an entity using `lt` instead of its own local transform,
+    /// and `nt` to transform its normals
+    fn draw_entity_transformed(&mut self, e: &Entity, lt: Mat4x4f, nt: Mat4x4f) {
+        if !e.is_visible() {
             return;
         }
-        let index = (y * self.width as i32 + x) as usize;
-        if self.depth_buffer[index] > depth {
+
+        // Skip entities that are entirely out of view
+        let triangle_count = e.shape.triangle_count() as u64;
+        self.stats.triangles_submitted += triangle_count;
+        let (min, max) = e.bounding_box_with(&lt);
+        if !self.frustum().intersects_box(min, max) {
+            self.stats.triangles_culled += triangle_count;
             return;
         }
-        self.pixels[index] = Some(color);
-        self.depth_buffer[index] = depth;
-    }
 
-    pub fn draw_entity(&mut self, e: &Entity) {
-        
-        let light_direction = Vec3f::new(1.0, -1.0, -1.0).normalize();
+        // Lighting is done in world space: face normals are computed
+        // from the transformed positions, vertex normals are transformed
+        // by the normal transform and the light is turned into world
+        // space, so rotating an entity changes which of its faces are lit
+        let light_direction = self.light_direction();
 
-        for tri in e.shape.triangles() {
+        // Moved out of self while drawing so that it can be called
+        // while pixels are being set
+        let shade_fn = self.shade_fn.take();
+        let fog = self.fog;
 
-            // Get vertices
-            let v0 = e.shape.get(tri.0);
-            let v1 = e.shape.get(tri.1);
-            let v2 = e.shape.get(tri.2);            
+        let vt = self.camera.view_matrix();
+
+        for (ti, tri) in e.shape.triangles().enumerate() {
+
+            // Get vertices, skipping triangles pointing outside the shape
+            let (v0, v1, v2) = match (
+                e.shape.try_get(tri.0),
+                e.shape.try_get(tri.1),
+                e.shape.try_get(tri.2),
+            ) {
+                (Some(v0), Some(v1), Some(v2)) => (v0, v1, v2),
+                _ => continue,
+            };
 
             // Apply local transform
-            let lt = e.gen_local_transform();
             let tp0 = lt.vecmul(&v0.position, true);
             let tp1 = lt.vecmul(&v1.position, true);
             let tp2 = lt.vecmul(&v2.position, true);
-
-            // Cull back faces
             let face_normal = (tp1 - tp0).cross(&(tp2 - tp0)).normalize();
-            if face_normal.dot(&(self.camera.position - tp0)) < 0.0 {
+
+            // Cull in view space, where the camera sits at the origin
+            let vp0 = vt.vecmul(&tp0, true);
+            let vp1 = vt.vecmul(&tp1, true);
+            let vp2 = vt.vecmul(&tp2, true);
+            let view_normal = (vp1 - vp0).cross(&(vp2 - vp0));
+            let faces_camera = view_normal.dot(&-vp0) >= 0.0;
+            let culled = match self.cull_mode {
+                CullMode::Back => !faces_camera,
+                CullMode::Front => faces_camera,
+                CullMode::None => false,
+            };
+            if culled {
+                self.stats.triangles_culled += 1;
                 continue;
             }
 
-            // This is wrong. TODO: Transform normals with (M^-1)^T instead
-            // Might explain the visual artifacts
-            // let n0 = lt.vecmul(&v0.normal, false).normalize();
-            // let n1 = lt.vecmul(&v1.normal, false).normalize();
-            // let n2 = lt.vecmul(&v2.normal, false).normalize();
-
-            // Project into a 2x2x2 box
-            let mut tp0 = self.projection_matrix.vecmul(&tp0, true);
-            let mut tp1 = self.projection_matrix.vecmul(&tp1, true);
-            let mut tp2 = self.projection_matrix.vecmul(&tp2, true);
-
-            // All values are in the interval [-1, 1]
-            tp0.x = (tp0.x + 1.0) * self.width as f32 / 2.0;
-            tp0.y = (tp0.y + 1.0) * self.height as f32 / 2.0;
-            tp1.x = (tp1.x + 1.0) * self.width as f32 / 2.0;
-            tp1.y = (tp1.y + 1.0) * self.height as f32 / 2.0;
-            tp2.x = (tp2.x + 1.0) * self.width as f32 / 2.0;
-            tp2.y = (tp2.y + 1.0) * self.height as f32 / 2.0;
+            // Keep the transformed positions around for the shader
+            let (wp0, wp1, wp2) = (tp0, tp1, tp2);
+
+            let tp0 = self.view_to_screen(&vp0);
+            let tp1 = self.view_to_screen(&vp1);
+            let tp2 = self.view_to_screen(&vp2);
 
             let depth = (tp0.z + tp1.z + tp2.z) / 3.0;
 
-            let brightness = (-face_normal.dot(&light_direction)).clamp(0.0, 1.0);
+            // Diffuse lighting, never darker than the ambient minimum
+            let diffuse = -face_normal.dot(&light_direction);
+            let brightness = diffuse.clamp(self.min_brightness, 1.0);
 
-            // Sample texture colors, will be white if texcoords are
-            // not defined
-            let c0 = if let Some(tc) = v0.texcoord {
-                e.sample_texture(tc)
+            // Specular highlight, strongest where the light is reflected
+            // straight towards the camera
+            let (specular_color, shininess) = e.get_specular();
+            let specular = if diffuse > 0.0 && specular_color.is_not_black() {
+                let center = (wp0 + wp1 + wp2).scale(1.0 / 3.0);
+                let to_camera = (self.camera.position - center).normalize();
+                let reflected = light_direction.reflect(&face_normal);
+                ColorF::from(specular_color) * reflected.dot(&to_camera).max(0.0).powf(shininess)
             } else {
-                Color::WHITE
+                ColorF::BLACK
+            };
+
+            // Use the triangle's material if it has one, otherwise sample
+            // texture colors, will be white if texcoords are not defined
+            let c0 = match e.shape.triangle_material(ti) {
+                Some(material) => material.sample(v0.texcoord),
+                None => if let Some(tc) = v0.texcoord {
+                    e.sample_texture(tc)
+                } else {
+                    Color::WHITE
+                },
+            } * e.get_tint();
+            // Light is summed unclamped and only clamped once it's done
+            let c0 = ColorF::from(c0);
+            let min_visible = self.min_visible;
+            let visible = |color: Color| match min_visible {
+                Some(min) => color.clamp_to_visible(min),
+                None => color,
             };
+            let lit = visible((c0 * brightness + specular).to_color());
             // let c1 = if let Some(tc) = v1.texcoord {
                 // e.sample_texture(tc)
             // } else {
@@ -100,15 +394,170 @@ impl Canvas {
                 // Color::WHITE
             // };
             
-            self.fill_triangle(
-                tp0.x as i32, tp0.y as i32, 
-                tp1.x as i32, tp1.y as i32, 
-                tp2.x as i32, tp2.y as i32,
-                c0 * brightness, depth
-            );
+            match self.render_mode {
+                RenderMode::Filled => {
+                    let gouraud = self.shading_mode == ShadingMode::Gouraud;
+                    let shaded = shade_fn.is_some() || fog.is_some() || gouraud;
+                    let normals = [
+                        nt.vecmul(&v0.normal, false).normalize(),
+                        nt.vecmul(&v1.normal, false).normalize(),
+                        nt.vecmul(&v2.normal, false).normalize(),
+                    ];
+                    // Lighting at every vertex, for Gouraud shading
+                    let vertex_brightness = normals.map(|n|
+                        (-n.dot(&light_direction)).clamp(self.min_brightness, 1.0)
+                    );
+                    let texcoords = match (v0.texcoord, v1.texcoord, v2.texcoord) {
+                        (Some(t0), Some(t1), Some(t2)) => Some([t0, t1, t2]),
+                        _ => None,
+                    };
+                    let (sx0, sy0) = (tp0.x as i32, tp0.y as i32);
+                    let (sx1, sy1) = (tp1.x as i32, tp1.y as i32);
+                    let (sx2, sy2) = (tp2.x as i32, tp2.y as i32);
+                    let mut color_at = |x, y| {
+                        if !shaded {
+                            return lit;
+                        }
+                        let (w0, w1, w2) = barycentric(
+                            (sx0, sy0), (sx1, sy1), (sx2, sy2), (x, y)
+                        );
+                        let base_color = if gouraud {
+                            (c0 * (vertex_brightness[0] * w0
+                                + vertex_brightness[1] * w1
+                                + vertex_brightness[2] * w2) + specular).to_color()
+                        } else {
+                            lit
+                        };
+                        let color = match &shade_fn {
+                            Some(shade) => shade(ShadeInput {
+                                position: wp0.scale(w0) + wp1.scale(w1) + wp2.scale(w2),
+                                normal: (normals[0].scale(w0)
+                                    + normals[1].scale(w1)
+                                    + normals[2].scale(w2)).normalize(),
+                                texcoord: texcoords.map(|[t0, t1, t2]| (
+                                    t0.0 * w0 + t1.0 * w1 + t2.0 * w2,
+                                    t0.1 * w0 + t1.1 * w1 + t2.1 * w2,
+                                )),
+                                color: base_color,
+                            }),
+                            None => base_color,
+                        };
+                        visible(match fog {
+                            // The camera looks down -Z in view space
+                            Some(fog) => fog.apply(color, -(vp0.z * w0 + vp1.z * w1 + vp2.z * w2)),
+                            None => color,
+                        })
+                    };
+                    if self.antialias {
+                        self.fill_triangle_coverage(
+                            [(tp0.x, tp0.y), (tp1.x, tp1.y), (tp2.x, tp2.y)],
+                            depth, color_at,
+                        );
+                    } else if let Some(threshold) = self.subdivision_threshold {
+                        self.fill_triangle_subdivided(
+                            [tp0, tp1, tp2], threshold, MAX_SUBDIVISION_LEVELS, &mut color_at,
+                        );
+                    } else if shaded {
+                        self.fill_triangle_with([(sx0, sy0), (sx1, sy1), (sx2, sy2)], depth, color_at);
+                    } else {
+                        self.fill_triangle(sx0, sy0, sx1, sy1, sx2, sy2, lit, depth);
+                    }
+                },
+                RenderMode::Wireframe => self.draw_triangle(
+                    tp0.x as i32, tp0.y as i32, 
+                    tp1.x as i32, tp1.y as i32, 
+                    tp2.x as i32, tp2.y as i32,
+                    lit, depth
+                ),
+                RenderMode::Points => {
+                    self.set(tp0.x as i32, tp0.y as i32, lit, depth);
+                    self.set(tp1.x as i32, tp1.y as i32, lit, depth);
+                    self.set(tp2.x as i32, tp2.y as i32, lit, depth);
+                },
+            }
+        }
+
+        self.shade_fn = shade_fn;
+    }
+
+    /// Sets the fog blended into the pixels filled by `draw_entity`.
+    /// `None` disables fog.
+    pub fn set_fog(&mut self, fog: Option<Fog>) {
+        self.fog = fog;
+    }
+
+    /// Sets the ambient occlusion `to_s` darkens creases and the
+    /// surroundings of edges with, with `depth_range` in world units.
+    /// `None`, the default, disables it.
+    pub fn set_ambient_occlusion(&mut self, ambient_occlusion: Option<AmbientOcclusion>) {
+        self.ambient_occlusion = ambient_occlusion;
+        self.update_ambient_occlusion();
+    }
+
+    /// Passes the ambient occlusion on to the frame buffer, converting
+    /// its depth range into depth buffer units, see `normalize_depth`
+    fn update_ambient_occlusion(&mut self) {
+        let scale = 2.0 / (self.zfar - self.znear);
+        self.frame.set_ambient_occlusion(self.ambient_occlusion.map(|ao| AmbientOcclusion {
+            depth_range: ao.depth_range * scale,
+            ..ao
+        }));
+    }
+
+    /// Sets a function computing the color of every pixel filled by
+    /// `draw_entity`, replacing the flat shaded color. `None` restores
+    /// the default shading.
+    pub fn set_shade_fn(&mut self, shade_fn: Option<Box<dyn Fn(ShadeInput) -> Color>>) {
+        self.shade_fn = shade_fn;
+    }
+
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        self.render_mode = render_mode;
+    }
+
+    pub fn get_render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+
+    pub fn set_shading_mode(&mut self, shading_mode: ShadingMode) {
+        self.shading_mode = shading_mode;
+    }
+
+    pub fn get_shading_mode(&self) -> ShadingMode {
+        self.shading_mode
+    }
+
+    /// Sets which space the light's direction is fixed in, see
+    /// `LightSpace`
+    pub fn set_light_space(&mut self, light_space: LightSpace) {
+        self.light_space = light_space;
+    }
+
+    pub fn get_light_space(&self) -> LightSpace {
+        self.light_space
+    }
+
+    /// Returns the direction the light travels in, in world space
+    fn light_direction(&self) -> Vec3f {
+        let direction = Vec3f::new(1.0, -1.0, -1.0).normalize();
+        match self.light_space {
+            LightSpace::World => direction,
+            // In view space x is right, y is up and the camera looks
+            // down -z
+            LightSpace::View => self.camera.right().scale(direction.x)
+                + self.camera.up().scale(direction.y)
+                + self.camera.direction.normalize().scale(-direction.z),
         }
     }
 
+    pub fn set_cull_mode(&mut self, cull_mode: CullMode) {
+        self.cull_mode = cull_mode;
+    }
+
+    pub fn get_cull_mode(&self) -> CullMode {
+        self.cull_mode
+    }
+
     pub fn draw_line(&mut self, 
         x0: i32, y0: i32, 
         x1: i32, y1: i32, 
@@ -143,6 +592,169 @@ impl Canvas {
         }
     }
 
+    /// Draws line segments given in world space, such as the ones from
+    /// `shapes::make_grid`. Segments are clipped at the near plane and
+    /// at the edges of the canvas, so that ends behind the camera or far
+    /// off screen only shorten the line.
+    pub fn draw_lines(&mut self, lines: &[(Vec3f, Vec3f, Color)]) {
+        let view_matrix = self.camera.view_matrix();
+        // The camera looks down -Z in view space
+        let near_z = -self.znear;
+        for (start, end, color) in lines {
+            let mut a = view_matrix.vecmul(start, true);
+            let mut b = view_matrix.vecmul(end, true);
+            if a.z > near_z && b.z > near_z {
+                continue;
+            }
+            if a.z > near_z {
+                a = b + (a - b).scale((near_z - b.z) / (a.z - b.z));
+            } else if b.z > near_z {
+                b = a + (b - a).scale((near_z - a.z) / (b.z - a.z));
+            }
+            let (a, b) = (self.view_to_screen(&a), self.view_to_screen(&b));
+            let max = (self.frame.width as f32 - 1.0, self.frame.height as f32 - 1.0);
+            if let Some(((x0, y0), (x1, y1))) = clip_line((a.x, a.y), (b.x, b.y), max) {
+                self.draw_line(
+                    x0.floor() as i32, y0.floor() as i32,
+                    x1.floor() as i32, y1.floor() as i32,
+                    *color, (a.z + b.z) / 2.0,
+                );
+            }
+        }
+    }
+
+    /// Draws the edges of the axis-aligned box between the corners `min`
+    /// and `max`, given in world space, such as an entity's bounding box.
+    /// Edges are clipped like in `draw_lines`, so boxes around or right
+    /// in front of the camera only lose the parts out of view.
+    pub fn draw_aabb(&mut self, min: Vec3f, max: Vec3f, color: Color) {
+        // Bit 0, 1 and 2 of the index pick max over min for x, y and z
+        let corner = |i: usize| Vec3f::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        );
+        // Every edge joins two corners differing in one bit
+        let mut edges = Vec::with_capacity(12);
+        for i in 0..8 {
+            for bit in &[1, 2, 4] {
+                if i & bit == 0 {
+                    edges.push((corner(i), corner(i | bit), color));
+                }
+            }
+        }
+        self.draw_lines(&edges);
+    }
+
+    /// Draws a line `thickness` pixels wide by drawing parallel copies
+    /// of it, offset across its minor axis.
+    pub fn draw_line_thick(&mut self, 
+        start: (i32, i32), end: (i32, i32), 
+        color: Color, depth: f32, thickness: i32) 
+    {
+        let ((x0, y0), (x1, y1)) = (start, end);
+        let horizontal = (x1 - x0).abs() > (y1 - y0).abs();
+        for offset in -(thickness - 1) / 2..=thickness / 2 {
+            if horizontal {
+                self.draw_line(x0, y0 + offset, x1, y1 + offset, color, depth);
+            } else {
+                self.draw_line(x0 + offset, y0, x1 + offset, y1, color, depth);
+            }
+        }
+    }
+
+    /// Draws the outline of the `w`x`h` rectangle with its top left
+    /// corner at (`x`, `y`)
+    pub fn draw_rect(&mut self, 
+        x: i32, y: i32, w: i32, h: i32, 
+        color: Color, depth: f32) 
+    {
+        if w <= 0 || h <= 0 {
+            return;
+        }
+        let (x1, y1) = (x + w - 1, y + h - 1);
+        for px in x..=x1 {
+            self.set(px, y, color, depth);
+            self.set(px, y1, color, depth);
+        }
+        for py in y..=y1 {
+            self.set(x, py, color, depth);
+            self.set(x1, py, color, depth);
+        }
+    }
+
+    /// Fills the `w`x`h` rectangle with its top left corner at (`x`, `y`)
+    pub fn fill_rect(&mut self, 
+        x: i32, y: i32, w: i32, h: i32, 
+        color: Color, depth: f32) 
+    {
+        for py in y..y + h {
+            for px in x..x + w {
+                self.set(px, py, color, depth);
+            }
+        }
+    }
+
+    /// Stretches `texture` across the `(x, y, w, h)` rectangle, or the
+    /// whole canvas if `rect` is `None`. The pixels are at the far plane
+    /// (depth -1), so anything drawn afterwards ends up in front.
+    pub fn blit_texture(&mut self, texture: &Texture, rect: Option<(i32, i32, i32, i32)>) {
+        let (x, y, w, h) = rect.unwrap_or((0, 0, self.frame.width as i32, self.frame.height as i32));
+        for py in y.max(0)..(y + h).min(self.frame.height as i32) {
+            let v = (py - y) as f32 / (h - 1).max(1) as f32;
+            for px in x.max(0)..(x + w).min(self.frame.width as i32) {
+                let u = (px - x) as f32 / (w - 1).max(1) as f32;
+                self.set(px, py, texture.sample(u, v), -1.0);
+            }
+        }
+    }
+
+    /// Draws the outline of a circle using the midpoint circle algorithm
+    pub fn draw_circle(&mut self, 
+        cx: i32, cy: i32, radius: i32, 
+        color: Color, depth: f32) 
+    {
+        let (mut x, mut y) = (radius, 0);
+        let mut err = 1 - radius;
+        while x >= y {
+            // Every step sets one point in each octant
+            for (dx, dy) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+                self.set(cx + dx, cy + dy, color, depth);
+            }
+            y += 1;
+            if err < 0 {
+                err += 2 * y + 1;
+            } else {
+                x -= 1;
+                err += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    /// Fills a circle by drawing horizontal spans between the points
+    /// of the midpoint circle
+    pub fn fill_circle(&mut self, 
+        cx: i32, cy: i32, radius: i32, 
+        color: Color, depth: f32) 
+    {
+        let (mut x, mut y) = (radius, 0);
+        let mut err = 1 - radius;
+        while x >= y {
+            for (dx, dy) in [(x, y), (x, -y), (y, x), (y, -x)] {
+                for px in cx - dx..=cx + dx {
+                    self.set(px, cy + dy, color, depth);
+                }
+            }
+            y += 1;
+            if err < 0 {
+                err += 2 * y + 1;
+            } else {
+                x -= 1;
+                err += 2 * (y - x) + 1;
+            }
+        }
+    }
+
     pub fn draw_triangle(&mut self, 
         x0: i32, y0: i32, 
         x1: i32, y1: i32,
@@ -160,10 +772,99 @@ impl Canvas {
         x2: i32, y2: i32,
         color: Color, depth: f32)
     {
+        self.fill_triangle_with([(x0, y0), (x1, y1), (x2, y2)], depth, |_, _| color);
+    }
+
+    /// Fills a triangle given in sub-pixel precision, recording how much
+    /// of each pixel it covers. The coverage is estimated from a 4x4 grid
+    /// of points inside every pixel.
+    fn fill_triangle_coverage<F: FnMut(i32, i32) -> Color>(&mut self, 
+        points: [(f32, f32); 3], 
+        depth: f32, mut color_at: F)
+    {
+        const GRID: usize = 4;
+        let [(ax, ay), (bx, by), (cx, cy)] = points;
+        let area = (bx - ax) * (cy - ay) - (cx - ax) * (by - ay);
+        if area == 0.0 {
+            return;
+        }
+        // Positive on the inside of each edge, whatever the winding
+        let edge = |x0: f32, y0: f32, x1: f32, y1: f32, px: f32, py: f32| {
+            ((x1 - x0) * (py - y0) - (px - x0) * (y1 - y0)) * area.signum()
+        };
+        let min_x = ax.min(bx).min(cx).floor().max(0.0) as i32;
+        let min_y = ay.min(by).min(cy).floor().max(0.0) as i32;
+        let max_x = ax.max(bx).max(cx).ceil().min(self.frame.width as f32 - 1.0) as i32;
+        let max_y = ay.max(by).max(cy).ceil().min(self.frame.height as f32 - 1.0) as i32;
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let mut inside = 0;
+                for i in 0..GRID * GRID {
+                    let px = x as f32 + ((i % GRID) as f32 + 0.5) / GRID as f32;
+                    let py = y as f32 + ((i / GRID) as f32 + 0.5) / GRID as f32;
+                    if edge(ax, ay, bx, by, px, py) >= 0.0
+                        && edge(bx, by, cx, cy, px, py) >= 0.0
+                        && edge(cx, cy, ax, ay, px, py) >= 0.0
+                    {
+                        inside += 1;
+                    }
+                }
+                if inside > 0 {
+                    let coverage = inside as f32 / (GRID * GRID) as f32;
+                    self.set_with_coverage(x, y, color_at(x, y), depth, coverage);
+                }
+            }
+        }
+    }
+
+    /// Fills the triangle between the screen points, with depths as z,
+    /// splitting it at the midpoints of its edges until it spans at most
+    /// `threshold` pixels in either direction. Splitting stops after
+    /// `levels` halvings, and parts entirely off the canvas are skipped.
+    fn fill_triangle_subdivided<F: FnMut(i32, i32) -> Color>(&mut self, 
+        points: [Vec3f; 3], 
+        threshold: u32, levels: u32, color_at: &mut F)
+    {
+        let [p0, p1, p2] = points;
+        let (min_x, max_x) = (p0.x.min(p1.x).min(p2.x), p0.x.max(p1.x).max(p2.x));
+        let (min_y, max_y) = (p0.y.min(p1.y).min(p2.y), p0.y.max(p1.y).max(p2.y));
+        if max_x < 0.0 || max_y < 0.0
+            || min_x >= self.frame.width as f32 || min_y >= self.frame.height as f32
+        {
+            return;
+        }
+        let span = (max_x - min_x).max(max_y - min_y);
+        if levels == 0 || !span.is_finite() || span <= threshold as f32 {
+            let depth = (p0.z + p1.z + p2.z) / 3.0;
+            self.fill_triangle_with(
+                [(p0.x as i32, p0.y as i32), (p1.x as i32, p1.y as i32), (p2.x as i32, p2.y as i32)],
+                depth, &mut *color_at
+            );
+            return;
+        }
+        // Neighbors split their shared edge at the same point, so the
+        // smaller triangles line up without gaps
+        let m01 = (p0 + p1).scale(0.5);
+        let m12 = (p1 + p2).scale(0.5);
+        let m20 = (p2 + p0).scale(0.5);
+        let levels = levels - 1;
+        self.fill_triangle_subdivided([p0, m01, m20], threshold, levels, color_at);
+        self.fill_triangle_subdivided([m01, p1, m12], threshold, levels, color_at);
+        self.fill_triangle_subdivided([m20, m12, p2], threshold, levels, color_at);
+        self.fill_triangle_subdivided([m01, m12, m20], threshold, levels, color_at);
+    }
+
+    /// Fills a triangle like `fill_triangle`, but computes the color
+    /// of every pixel with `color_at(x, y)`.
+    fn fill_triangle_with<F: FnMut(i32, i32) -> Color>(&mut self, 
+        points: [(i32, i32); 3], 
+        depth: f32, mut color_at: F)
+    {
+        let [(x0, y0), (x1, y1), (x2, y2)] = points;
         // Fill in end points
-        self.set(x0, y0, color, depth);
-        self.set(x1, y1, color, depth);
-        self.set(x2, y2, color, depth);
+        self.set(x0, y0, color_at(x0, y0), depth);
+        self.set(x1, y1, color_at(x1, y1), depth);
+        self.set(x2, y2, color_at(x2, y2), depth);
 
         // Sort points by y-coord
         let (x0, y0, x1, y1) = if y0 < y1 {
@@ -181,34 +882,22 @@ impl Canvas {
         } else {
             (x2, y2, x1, y1)
         };
-        // Now (x0, y0) is always lowest
-        let dy = (y2 - y0 + 1) as usize;
-
-        let mut x012 = Vec::with_capacity(dy);
-        // println!("y0={}, y1={}, y2={}\r", y0, y1, y2);
-        for y in y0..y1 {
-            x012.push(plerp(y0, x0, y1, x1, y));
-        }
-        for y in y1..y2 + 1 {
-            x012.push(plerp(y1, x1, y2, x2, y));
-        }
-
-        let mut x02 = Vec::with_capacity(dy);
-        for y in y0..y2 + 1 {
-            // println!("y={}, plerp={}\r", y, plerp(y0, x0, y2, x2, y));
-            x02.push(plerp(y0, x0, y2, x2, y));
-        }
-
-        let m = x02.len() / 2;
-        let (x_left, x_right) = if x02[m] < x012[m] {
-            (x02, x012)
-        } else {
-            (x012, x02)
-        };
-        for y in y0..y2 + 1 {
-            let i = (y - y0) as usize;
-            for x in x_left[i]..x_right[i] + 1 {
-                self.set(x, y, color, depth);
+        // Now (x0, y0) is always lowest. Only the rows and columns on
+        // the canvas are visited, however far off it the corners are.
+        let max_x = self.frame.width as i32 - 1;
+        let max_y = self.frame.height as i32 - 1;
+        // Which edge is on the left is decided for every row, since
+        // rounding can make the edges touch or swap near the vertices
+        for y in y0.max(0)..=y2.min(max_y) {
+            let x012 = if y < y1 {
+                plerp(y0, x0, y1, x1, y)
+            } else {
+                plerp(y1, x1, y2, x2, y)
+            };
+            let x02 = plerp(y0, x0, y2, x2, y);
+            let (x_left, x_right) = (x02.min(x012), x02.max(x012));
+            for x in x_left.max(0)..=x_right.min(max_x) {
+                self.set(x, y, color_at(x, y), depth);
             }
         }
 
@@ -233,88 +922,1300 @@ impl Canvas {
     }
 
     pub fn clear(&mut self) {
-        let pixs = self.width * self.height;
-        self.pixels = vec![None; pixs];
-        self.depth_buffer = vec![f32::MIN; pixs];
+        self.frame.clear();
+        self.stats = FrameStats::default();
+    }
+
+    /// Returns what has been drawn since the last `clear`
+    pub fn last_frame_stats(&self) -> FrameStats {
+        let (pixels_set, pixels_depth_rejected) = self.frame.pixel_counts();
+        FrameStats { pixels_set, pixels_depth_rejected, ..self.stats }
+    }
+
+    /// Returns the pixels the canvas draws to
+    pub fn get_frame_buffer(&self) -> &FrameBuffer {
+        &self.frame
+    }
+
+    pub fn get_frame_buffer_mut(&mut self) -> &mut FrameBuffer {
+        &mut self.frame
+    }
+
+    /// Writes `text` on top of the rendered image, starting at the
+    /// character cell (`col`, `row`). Both are 0-based. The text is
+    /// kept until the next `clear`.
+    pub fn draw_text(&mut self, col: u16, row: u16, text: &str, color: Color) {
+        self.frame.draw_text(col, row, text, color);
+    }
+
+    /// Returns the rendered pixels row by row, top to bottom. Pixels
+    /// that haven't been drawn to are black. The buffer is
+    /// `width * height` long, see `size`.
+    pub fn render_to_rgb(&self) -> Vec<[u8; 3]> {
+        self.frame.render_to_rgb()
+    }
+
+    /// Renders `factor`x`factor` samples for every braille dot, which
+    /// are averaged into the dot's color to smooth out edges. Drawing
+    /// coordinates, `size` and `render_to_rgb` are in samples. Clears
+    /// the canvas.
+    pub fn set_sample_factor(&mut self, factor: usize) {
+        self.frame.set_sample_factor(factor);
+        self.stats = FrameStats::default();
+    }
+
+    /// Resizes the canvas to `width`x`height` pixels, keeping its
+    /// supersampling and field of view. Clears the canvas.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.frame.resize(width, height);
+        self.set_projection(self.fov, self.znear, self.zfar);
+        self.stats = FrameStats::default();
+    }
+
+    pub fn get_sample_factor(&self) -> usize {
+        self.frame.get_sample_factor()
+    }
+
+    /// Returns the size of the canvas in samples as (width, height).
+    /// Without supersampling every sample is a pixel.
+    pub fn size(&self) -> (usize, usize) {
+        self.frame.size()
     }
 
     /// Computes the resulting image as a string to be printed
     pub fn to_s(&self) -> String {
-        const INDEX_OFFSETS: [(usize, usize); 8] = [
-            (0, 0), (0, 1), (0, 2),
-            (1, 0), (1, 1), (1, 2),
-            (0, 3), (1, 3),
-        ];
-        let mut string = String::with_capacity(self.pixels.len() * 3 / 2 + 4);
-        string.write_str(&clear::All.to_string()).unwrap();
-        for row in 0..self.height / 4 {
-            for col in 0..self.width / 2 {
-                let (pix_row, pix_col) = (row * 4, col * 2);
-                let mut braille_code = 0x2800;
-                let mut cel_color = Color::BLACK;
-                for (i, (dx, dy)) in INDEX_OFFSETS.iter().enumerate() {
-                    let index = (pix_row + dy) * self.width + pix_col + dx;
-                    if let Some(p_color) = self.pixels[index] {
-                        braille_code += 1 << i;
-                        cel_color += p_color * (1.0 / 8.0);
-                    }
-                }
-                if braille_code != 0x2800 {
-                    string.write_str(&cursor::Goto(
-                        (col as u16).saturating_add(1), 
-                        (row as u16).saturating_add(1)).to_string()
-                    ).unwrap();
-                    string.write_str(&Rgb(cel_color.r, cel_color.g, cel_color.b).fg_string()).unwrap();
-                    string.write_char(char::from_u32(braille_code).unwrap()).unwrap();
-                }
-            }
-        }
-        string
+        self.frame.to_s()
+    }
+
+    /// Writes the resulting image to `out`, which may be any writer such
+    /// as stdout, a file or a buffer, see `to_s`
+    pub fn write_to<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        self.frame.write_to(out)
+    }
+
+    /// Writes the resulting image to `out` using the escape sequences
+    /// of backend `B`
+    pub fn write_to_with<B: Backend, W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        self.frame.write_to_with::<B, W>(out)
+    }
+
+    /// Computes the resulting image as a string to be printed, using
+    /// the escape sequences of backend `B`
+    pub fn to_s_with<B: Backend>(&self) -> String {
+        self.frame.to_s_with::<B>()
+    }
+
+    /// Sets whether `to_s` shows the depth buffer as shades of gray,
+    /// nearer being brighter, instead of the rendered colors.
+    pub fn set_depth_view(&mut self, depth_view: bool) {
+        self.frame.set_depth_view(depth_view);
     }
-    
+
+    /// Sets the gamma applied to every cell color by `to_s`. Values
+    /// above 1.0 brighten mid-tones, 1.0 leaves colors unchanged.
+    pub fn set_gamma(&mut self, gamma: f32) {
+        self.frame.set_gamma(gamma);
+    }
+
+    /// Sets which colors `to_s` may emit. Cell colors are quantized to
+    /// the nearest palette entry when the terminal lacks truecolor.
+    pub fn set_color_depth(&mut self, color_depth: ColorDepth) {
+        self.frame.set_color_depth(color_depth);
+    }
+
     pub fn new() -> Self {
-        // let wpos = Command::new("sh")
-            // .arg("-c")
-            // .arg(r"xdotool getwindowfocus getwindowgeometry --shell | sed /[XYHT]=/P -n | echo -n $(tr -dc '0-9\n')")
-            // .output()
-            // .expect("failed when getting window position.")
-            // .stdout;
-        // let wpos: Vec<i32> = from_utf8(&wpos).unwrap().split(" ").map(|d|
-            // d.parse::<i32>().unwrap()
-        // ).collect();
-        // let (win_x, win_y, pix_w, pix_h) = (wpos[0], wpos[1], wpos[2] - 13, wpos[3] - 10);
-
-        let (win_x, win_y, pix_w, pix_h) = (0, 0, 1353, 758);
-
-        let (width, height) = terminal_size().unwrap();
-        let (width, height) = (width as usize * 2, height as usize * 4);
-        let pixels = vec![None; width * height];
-        let projection_matrix = Mat4x4f::projection(
-            pix_w as f32 / pix_h as f32, 
-            90.0, 
-            0.1, 1000.0
+        Self::with_fov(90.0)
+    }
+
+    /// Creates a canvas filling the terminal, projecting with a field
+    /// of view of `fov` degrees.
+    pub fn with_fov(fov: f32) -> Self {
+        let (width, height) = DefaultBackend::terminal_size();
+        let mut canvas = Self::build(width as usize * 2, height as usize * 4, fov);
+        canvas.set_color_depth(ColorDepth::detect());
+        canvas
+    }
+
+    /// Creates a canvas of `width`x`height` pixels, independent of the
+    /// size of the terminal. Every braille character is 2x4 pixels.
+    pub fn with_size(width: usize, height: usize) -> Self {
+        Self::build(width, height, 90.0)
+    }
+
+    fn build(width: usize, height: usize, fov: f32) -> Self {
+        let (znear, zfar) = (0.1, 1000.0);
+        let cell_aspect = 0.5;
+        let projection_matrix = Mat4x4f::projection_fov(
+            fov, 
+            width as f32 / height as f32 * 2.0 * cell_aspect, 
+            znear, zfar
         );
-        let camera = Camera {
-            position: Vec3f::new(0.0, 0.0, 0.0),
-            direction: Vec3f::new(0.0, 0.0, 1.0),
-        };
-        let depth_buffer = vec![f32::MIN; width * height];
+        let camera = Camera::default();
         Self { 
-            pixels, 
-            width, 
-            height, 
-            win_x, 
-            win_y, 
-            pix_w, 
-            pix_h, 
+            frame: FrameBuffer::new(width, height),
+            cell_aspect, 
             projection_matrix, 
             camera,
-            depth_buffer,
+            render_mode: RenderMode::Filled,
+            cull_mode: CullMode::Back,
+            fog: None,
+            ambient_occlusion: None,
+            shade_fn: None,
+            min_brightness: 0.1,
+            min_visible: None,
+            antialias: false,
+            subdivision_threshold: None,
+            stats: FrameStats::default(),
+            shading_mode: ShadingMode::Flat,
+            light_space: LightSpace::World,
+            fov,
+            znear,
+            zfar,
         }
     }
 }
 
+/// Clips the line from `a` to `b` to the rectangle from (0, 0) to `max`
+/// with the Liang-Barsky algorithm. Returns `None` if none of the line
+/// is inside.
+fn clip_line(a: (f32, f32), b: (f32, f32), max: (f32, f32)) -> Option<((f32, f32), (f32, f32))> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    // Every edge as how fast the line leaves through it and how far
+    // inside of it the line starts
+    let edges = [(-dx, a.0), (dx, max.0 - a.0), (-dy, a.1), (dy, max.1 - a.1)];
+    for &(p, q) in &edges {
+        if !q.is_finite() || !p.is_finite() {
+            return None;
+        }
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    Some(((a.0 + t0 * dx, a.1 + t0 * dy), (a.0 + t1 * dx, a.1 + t1 * dy)))
+}
+
+/// Computes the barycentric weights of `p` in the triangle `a`, `b`, `c`.
+/// Degenerate triangles give all weight to `a`.
+fn barycentric(a: (i32, i32), b: (i32, i32), c: (i32, i32), p: (i32, i32)) -> (f32, f32, f32) {
+    let (ax, ay) = (a.0 as f32, a.1 as f32);
+    let (bx, by) = (b.0 as f32, b.1 as f32);
+    let (cx, cy) = (c.0 as f32, c.1 as f32);
+    let (px, py) = (p.0 as f32, p.1 as f32);
+    let area = (bx - ax) * (cy - ay) - (cx - ax) * (by - ay);
+    if area == 0.0 {
+        return (1.0, 0.0, 0.0);
+    }
+    let w1 = ((px - ax) * (cy - ay) - (cx - ax) * (py - ay)) / area;
+    let w2 = ((bx - ax) * (py - ay) - (px - ax) * (by - ay)) / area;
+    (1.0 - w1 - w2, w1, w2)
+}
+
+/// Fades geometry into `color` with its distance from the camera.
+/// Pixels nearer than `start` are unaffected and pixels at `end` or
+/// beyond are entirely `color`. With `end` at or before `start` the
+/// fog starts abruptly at `start`.
+#[derive(Clone, Copy)]
+pub struct Fog {
+    pub color: Color,
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Fog {
+    fn apply(&self, color: Color, distance: f32) -> Color {
+        let amount = if self.end > self.start {
+            ((distance - self.start) / (self.end - self.start)).clamp(0.0, 1.0)
+        } else if distance >= self.start {
+            1.0
+        } else {
+            0.0
+        };
+        color.lerp(self.color, amount)
+    }
+}
+
+/// Counts of the work done drawing a frame, see `Canvas::last_frame_stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    /// Triangles of the visible entities drawn
+    pub triangles_submitted: u64,
+    /// Triangles skipped for facing the wrong way or being out of view
+    pub triangles_culled: u64,
+    pub pixels_set: u64,
+    /// Pixels that failed the depth test
+    pub pixels_depth_rejected: u64,
+}
+
+/// The interpolated values passed to a shade function for every pixel
+pub struct ShadeInput {
+    /// The position after the entity's local transform
+    pub position: Vec3f,
+    pub normal: Vec3f,
+    /// The texture coordinates, if the vertices have any
+    pub texcoord: Option<(f32, f32)>,
+    /// The color the pixel would have without the shade function
+    pub color: Color,
+}
+
+/// How `draw_entity` rasterizes the triangles of an entity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderMode {
+    Filled,
+    Wireframe,
+    Points,
+}
+
+impl RenderMode {
+    /// Returns the mode after this one, wrapping around to `Filled`.
+    pub fn next(self) -> Self {
+        match self {
+            RenderMode::Filled => RenderMode::Wireframe,
+            RenderMode::Wireframe => RenderMode::Points,
+            RenderMode::Points => RenderMode::Filled,
+        }
+    }
+}
+
+/// How `draw_entity` lights filled triangles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShadingMode {
+    /// One brightness per triangle, from the normal of its face
+    Flat,
+    /// Lights every vertex using its normal and blends the brightness
+    /// across the triangle, which makes smooth meshes look smooth
+    Gouraud,
+}
+
+/// The space the light's direction is fixed in. Either way lighting is
+/// computed in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightSpace {
+    /// The light shines from the same direction however the camera
+    /// moves, like the sun
+    World,
+    /// The light shines from the same direction relative to the camera,
+    /// like a headlamp
+    View,
+}
+
+/// Which triangles `draw_entity` skips, based on whether they face
+/// the camera. Triangles with counter-clockwise winding face the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CullMode {
+    Back,
+    Front,
+    None,
+}
+
+/// The colors a terminal can display
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorDepth {
+    /// 24-bit RGB colors
+    TrueColor,
+    /// The 256-color xterm palette
+    Ansi256,
+    /// The 16 basic ANSI colors
+    Ansi16,
+}
+
+impl ColorDepth {
+    /// Guesses the color depth of the terminal from `$COLORTERM` and `$TERM`
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+}
+
+/// The comparison a pixel's depth has to pass against the depth
+/// already in the depth buffer to be drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthFunc {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Always,
+}
+
+impl DepthFunc {
+    pub(crate) fn passes(self, depth: f32, stored: f32) -> bool {
+        match self {
+            DepthFunc::Less => depth < stored,
+            DepthFunc::LessEqual => depth <= stored,
+            DepthFunc::Greater => depth > stored,
+            DepthFunc::GreaterEqual => depth >= stored,
+            DepthFunc::Always => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct Camera {
     pub position: Vec3f,
     pub direction: Vec3f,
-}
\ No newline at end of file
+    /// How far the view is tilted around `direction`, in radians,
+    /// counter-clockwise as seen through the camera
+    pub roll: f32,
+}
+
+impl Default for Camera {
+    /// A camera at the origin looking down -Z
+    fn default() -> Self {
+        Self {
+            position: Vec3f::zero(),
+            direction: Vec3f::new(0.0, 0.0, -1.0),
+            roll: 0.0,
+        }
+    }
+}
+
+impl Camera {
+    /// How far up or down the camera can look, just under 90 degrees so
+    /// that the view never flips over
+    const MAX_PITCH: f32 = 89.0 * PI / 180.0;
+
+    /// Creates a camera at `position` looking in the direction given by
+    /// `yaw` around +Y and `pitch` up from the XZ plane, both in radians.
+    /// Yaw and pitch 0 looks down +Z.
+    pub fn from_yaw_pitch(position: Vec3f, yaw: f32, pitch: f32) -> Self {
+        let pitch = pitch.clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+        let direction = Vec3f::new(
+            yaw.sin() * pitch.cos(),
+            pitch.sin(),
+            yaw.cos() * pitch.cos(),
+        );
+        Self { position, direction, roll: 0.0 }
+    }
+
+    /// Returns the (yaw, pitch) of the camera direction, in radians
+    pub fn yaw_pitch(&self) -> (f32, f32) {
+        let d = self.forward();
+        (d.x.atan2(d.z), d.y.clamp(-1.0, 1.0).asin())
+    }
+
+    /// Turns the camera by `d_yaw` and `d_pitch` radians, keeping its roll
+    pub fn rotate(&mut self, d_yaw: f32, d_pitch: f32) {
+        let (yaw, pitch) = self.yaw_pitch();
+        let roll = self.roll;
+        *self = Self::from_yaw_pitch(self.position, yaw + d_yaw, pitch + d_pitch);
+        self.roll = roll;
+    }
+
+    /// Tilts the view by `d_roll` radians around the direction
+    pub fn rotate_roll(&mut self, d_roll: f32) {
+        self.roll += d_roll;
+    }
+
+    /// Moves the camera `forward`, `right` and `up` along its own axes
+    pub fn move_relative(&mut self, forward: f32, right: f32, up: f32) {
+        self.position += self.forward().scale(forward)
+            + self.right().scale(right)
+            + self.up().scale(up);
+    }
+
+    /// Returns the normalized direction the camera is looking in
+    pub fn forward(&self) -> Vec3f {
+        self.direction.normalize()
+    }
+
+    /// Returns the normalized direction to the right of the view
+    pub fn right(&self) -> Vec3f {
+        let forward = self.forward();
+        forward.cross(&Vec3f::new(0.0, 1.0, 0.0))
+            .normalize()
+            .rotate_around_axis(-forward, self.roll)
+    }
+
+    /// Returns the normalized direction towards the top of the view
+    pub fn up(&self) -> Vec3f {
+        self.right().cross(&self.forward())
+    }
+
+    /// Returns the matrix transforming world space into view space
+    pub fn view_matrix(&self) -> Mat4x4f {
+        Mat4x4f::look_at(
+            self.position,
+            self.position + self.direction,
+            self.up(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    use crate::{shapes::{self, Shape}, texture::{TextureSlot, WrapMode}};
+
+    fn count_set(canvas: &Canvas) -> usize {
+        canvas.frame.pixels.iter().filter(|p| p.is_some()).count()
+    }
+
+    /// A single triangle facing the camera, covering the middle of the view
+    fn facing_triangle() -> Entity {
+        let mut entity = Entity::with_geometry(Shape::with_tris(
+            vec![
+                Vec3f::new(-1.0, -1.0, 0.0),
+                Vec3f::new(1.0, -1.0, 0.0),
+                Vec3f::new(0.0, 1.0, 0.0),
+            ],
+            vec![(0, 1, 2)],
+        ));
+        entity.set_translation(0.0, 0.0, -3.0);
+        entity
+    }
+
+    #[test]
+    fn render_to_rgb_places_pixel() {
+        let mut canvas = Canvas::with_size(8, 4);
+        canvas.clear();
+        canvas.set(3, 2, Color::RED, 0.0);
+
+        let (width, height) = canvas.size();
+        let rgb = canvas.render_to_rgb();
+        assert_eq!(rgb.len(), width * height);
+        assert_eq!(rgb[2 * width + 3], [255, 0, 0]);
+        assert_eq!(rgb.iter().filter(|p| **p != [0, 0, 0]).count(), 1);
+    }
+
+    #[test]
+    fn shade_fn_colors_every_filled_pixel() {
+        let mut canvas = Canvas::with_size(40, 40);
+        canvas.set_shade_fn(Some(Box::new(|_| Color::new(10, 200, 30))));
+        canvas.clear();
+        canvas.draw_entity(&facing_triangle());
+
+        let filled: Vec<_> = canvas.render_to_rgb().into_iter()
+            .filter(|p| *p != [0, 0, 0])
+            .collect();
+        assert!(!filled.is_empty());
+        assert!(filled.iter().all(|p| *p == [10, 200, 30]));
+    }
+
+    #[test]
+    fn cull_none_keeps_back_faces() {
+        // Same triangle as `facing_triangle` but wound the other way
+        let mut entity = Entity::with_geometry(Shape::with_tris(
+            vec![
+                Vec3f::new(-1.0, -1.0, 0.0),
+                Vec3f::new(0.0, 1.0, 0.0),
+                Vec3f::new(1.0, -1.0, 0.0),
+            ],
+            vec![(0, 1, 2)],
+        ));
+        entity.set_translation(0.0, 0.0, -3.0);
+        let mut canvas = Canvas::with_size(40, 40);
+        canvas.clear();
+        canvas.draw_entity(&entity);
+        assert_eq!(count_set(&canvas), 0);
+
+        canvas.set_cull_mode(CullMode::None);
+        canvas.clear();
+        canvas.draw_entity(&entity);
+        assert!(count_set(&canvas) > 0);
+    }
+
+    #[test]
+    fn depth_func_always_overwrites() {
+        let mut canvas = Canvas::with_size(4, 4);
+        canvas.clear();
+        canvas.set(1, 1, Color::RED, 10.0);
+        canvas.set(1, 1, Color::BLUE, 0.0);
+        assert_eq!(canvas.render_to_rgb()[4 + 1], [255, 0, 0]);
+
+        canvas.set_depth_func(DepthFunc::Always);
+        canvas.set(1, 1, Color::BLUE, 0.0);
+        assert_eq!(canvas.render_to_rgb()[4 + 1], [0, 0, 255]);
+    }
+
+    #[test]
+    fn thick_line_sets_more_pixels() {
+        let mut canvas = Canvas::with_size(20, 20);
+        canvas.clear();
+        canvas.draw_line_thick((10, 2), (10, 17), Color::WHITE, 0.0, 1);
+        let thin = count_set(&canvas);
+
+        canvas.clear();
+        canvas.draw_line_thick((10, 2), (10, 17), Color::WHITE, 0.0, 3);
+        let thick = count_set(&canvas);
+        assert_eq!(thick, 3 * thin);
+    }
+
+    #[test]
+    fn scissor_limits_drawing() {
+        let mut canvas = Canvas::with_size(20, 20);
+        canvas.set_scissor(Some((0, 0, 9, 9)));
+        canvas.clear();
+        canvas.fill_triangle(-20, -20, 60, -20, -20, 60, Color::WHITE, 0.0);
+
+        for (i, p) in canvas.frame.pixels.iter().enumerate() {
+            let (x, y) = (i % 20, i / 20);
+            assert_eq!(p.is_some(), x < 10 && y < 10, "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn circle_hits_cardinal_points() {
+        let mut canvas = Canvas::with_size(20, 20);
+        canvas.clear();
+        canvas.draw_circle(10, 10, 5, Color::WHITE, 0.0);
+
+        for (x, y) in [(15, 10), (5, 10), (10, 15), (10, 5)] {
+            assert!(canvas.frame.pixels[y * 20 + x].is_some(), "pixel ({}, {})", x, y);
+        }
+        assert!(canvas.frame.pixels[10 * 20 + 10].is_none());
+    }
+
+    #[test]
+    fn text_is_placed_at_cell() {
+        let mut canvas = Canvas::with_size(20, 20);
+        canvas.clear();
+        canvas.draw_text(3, 2, "hi", Color::RED);
+
+        let mut expected = String::new();
+        DefaultBackend::goto(&mut expected, 4, 3);
+        DefaultBackend::set_fg(&mut expected, Color::RED);
+        expected.push_str("hi");
+        assert!(canvas.to_s().ends_with(&expected));
+
+        canvas.clear();
+        assert!(!canvas.to_s().contains("hi"));
+    }
+
+    #[test]
+    fn fog_covers_triangle_at_end() {
+        let mut canvas = Canvas::with_size(40, 40);
+        let fog_color = Color::new(90, 100, 110);
+        canvas.set_fog(Some(Fog { color: fog_color, start: 1.0, end: 3.0 }));
+        canvas.clear();
+        canvas.draw_entity(&facing_triangle());
+
+        let filled: Vec<_> = canvas.frame.pixels.iter().flatten().collect();
+        assert!(!filled.is_empty());
+        assert!(filled.iter().all(|c| (c.r, c.g, c.b) == (90, 100, 110)));
+    }
+
+    #[test]
+    fn pix2cell_maps_window_onto_canvas() {
+        let canvas = Canvas::with_size(80, 40);
+        let window = (100, 50, 800, 400);
+        assert_eq!(canvas.pix2cell((100, 50), window), (0, 0));
+        assert_eq!(canvas.pix2cell((500, 250), window), (40, 20));
+    }
+
+    #[test]
+    fn fog_without_range_is_step() {
+        let fog = Fog { color: Color::WHITE, start: 2.0, end: 2.0 };
+        let near = fog.apply(Color::RED, 1.0);
+        let far = fog.apply(Color::RED, 2.0);
+        assert_eq!((near.r, near.g, near.b), (255, 0, 0));
+        assert_eq!((far.r, far.g, far.b), (255, 255, 255));
+    }
+
+    #[test]
+    fn depth_view_draws_nearer_brighter() {
+        let mut canvas = Canvas::with_size(20, 8);
+        canvas.set_depth_view(true);
+        canvas.clear();
+        canvas.fill_triangle(0, 0, 7, 0, 0, 7, Color::RED, 2.0);
+        canvas.fill_triangle(12, 0, 19, 0, 12, 7, Color::RED, 1.0);
+
+        let range = canvas.frame.depth_range();
+        let (_, near) = canvas.frame.cell(0, 0, range, None).unwrap();
+        let (_, far) = canvas.frame.cell(6, 0, range, None).unwrap();
+        assert!(near.r > far.r);
+    }
+
+    #[test]
+    fn tint_colors_untextured_entity() {
+        let mut canvas = Canvas::with_size(40, 40);
+        let mut entity = facing_triangle();
+        entity.set_tint(Color::RED);
+        canvas.clear();
+        canvas.draw_entity(&entity);
+
+        let filled: Vec<_> = canvas.frame.pixels.iter().flatten().collect();
+        assert!(!filled.is_empty());
+        assert!(filled.iter().all(|c| c.r > 0 && c.g == 0 && c.b == 0));
+    }
+
+    #[test]
+    fn invisible_entity_is_not_drawn() {
+        let mut canvas = Canvas::with_size(40, 40);
+        let mut entity = facing_triangle();
+        entity.set_visible(false);
+        canvas.clear();
+        canvas.draw_entity(&entity);
+        assert_eq!(count_set(&canvas), 0);
+    }
+
+    #[test]
+    fn entity_behind_camera_is_culled() {
+        let mut canvas = Canvas::with_size(40, 40);
+        let mut entity = facing_triangle();
+        assert!(canvas.is_in_view(&entity));
+
+        entity.set_translation(0.0, 0.0, 3.0);
+        assert!(!canvas.is_in_view(&entity));
+        canvas.set_cull_mode(CullMode::None);
+        canvas.clear();
+        canvas.draw_entity(&entity);
+        assert_eq!(count_set(&canvas), 0);
+    }
+
+    #[test]
+    fn entity_beside_view_is_culled() {
+        let canvas = Canvas::with_size(40, 40);
+        let mut entity = facing_triangle();
+        entity.set_translation(50.0, 0.0, -3.0);
+        assert!(!canvas.is_in_view(&entity));
+    }
+
+    #[test]
+    fn shared_shape_draws_at_each_transform() {
+        let shape = Rc::new(facing_triangle().shape.as_ref().clone());
+        let mut left = Entity::with_geometry_shared(Rc::clone(&shape));
+        let mut right = Entity::with_geometry_shared(Rc::clone(&shape));
+        left.set_translation(-1.5, 0.0, -3.0);
+        right.set_translation(1.5, 0.0, -3.0);
+        assert_eq!(Rc::strong_count(&shape), 3);
+
+        let mut canvas = Canvas::with_size(40, 40);
+        canvas.clear();
+        canvas.draw_entity(&left);
+        let left_pixels: Vec<bool> = canvas.frame.pixels.iter().map(|p| p.is_some()).collect();
+        canvas.clear();
+        canvas.draw_entity(&right);
+        let right_pixels: Vec<bool> = canvas.frame.pixels.iter().map(|p| p.is_some()).collect();
+
+        assert!(left_pixels.contains(&true));
+        assert!(right_pixels.contains(&true));
+        assert_ne!(left_pixels, right_pixels);
+    }
+
+    #[test]
+    fn camera_basis_from_yaw_pitch() {
+        let camera = Camera::from_yaw_pitch(Vec3f::zero(), 0.0, 0.0);
+        let forward = camera.forward();
+        assert!((forward.z - 1.0).abs() < 1e-5);
+        assert!(camera.right().dot(&forward).abs() < 1e-5);
+        assert!(camera.up().dot(&forward).abs() < 1e-5);
+        assert!((camera.up().y - 1.0).abs() < 1e-5);
+
+        let mut camera = camera;
+        camera.rotate(0.5, 10.0);
+        let (yaw, pitch) = camera.yaw_pitch();
+        assert!((yaw - 0.5).abs() < 1e-4);
+        assert!(pitch < PI / 2.0 && pitch > 1.5);
+    }
+
+    #[test]
+    fn supersampling_gives_intermediate_edges() {
+        // Every cell color without supersampling is a multiple of 255/8
+        let edge_colors = |factor: usize| {
+            let mut canvas = Canvas::with_size(16, 16);
+            canvas.set_sample_factor(factor);
+            let n = 16 * factor as i32 - 1;
+            canvas.fill_triangle(0, 0, n, 0, 0, n, Color::WHITE, 0.0);
+            (0..4).flat_map(|row| (0..8).map(move |col| (col, row)))
+                .filter_map(|(col, row)| canvas.frame.cell(col, row, None, None))
+                .map(|(_, color)| color.r as u32)
+                .collect::<Vec<_>>()
+        };
+        let is_eighth = |r: &u32| (0..=8).any(|n| 255 * n / 8 == *r);
+        assert!(edge_colors(1).iter().all(is_eighth));
+        assert!(!edge_colors(2).iter().all(is_eighth));
+    }
+
+    #[test]
+    fn fill_rect_sets_every_pixel_inside() {
+        let mut canvas = Canvas::with_size(16, 16);
+        canvas.clear();
+        canvas.fill_rect(2, 3, 5, 4, Color::RED, 0.0);
+        assert_eq!(count_set(&canvas), 5 * 4);
+
+        // Clipped by the edge of the canvas
+        canvas.clear();
+        canvas.fill_rect(12, 12, 10, 10, Color::RED, 0.0);
+        assert_eq!(count_set(&canvas), 4 * 4);
+
+        canvas.clear();
+        canvas.draw_rect(2, 3, 5, 4, Color::RED, 0.0);
+        assert_eq!(count_set(&canvas), 5 * 4 - 3 * 2);
+    }
+
+    #[test]
+    fn grazed_pixel_dims_the_cell() {
+        let mut canvas = Canvas::with_size(2, 4);
+        canvas.clear();
+        canvas.set(0, 0, Color::WHITE, 0.0);
+        let (full_char, full) = canvas.frame.cell(0, 0, None, None).unwrap();
+
+        // Covers half of pixel (0, 0)
+        canvas.clear();
+        canvas.fill_triangle_coverage([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)], 0.0, |_, _| Color::WHITE);
+        let (grazed_char, grazed) = canvas.frame.cell(0, 0, None, None).unwrap();
+
+        assert_eq!(grazed_char, full_char);
+        assert!(grazed.r > 0 && grazed.r < full.r);
+    }
+
+    #[test]
+    fn blend_red_over_blue() {
+        let mut canvas = Canvas::with_size(4, 4);
+        canvas.clear();
+        canvas.set(1, 1, Color::BLUE, 0.0);
+        canvas.set_blended(1, 1, Color::RED, 0.5, 1.0);
+
+        let blended = canvas.frame.pixels[5].unwrap();
+        assert!(blended.approx_eq(&Color::new(128, 0, 128), 1));
+        // Translucent colors don't hide what's behind them
+        assert_eq!(canvas.frame.depth[5], 0.0);
+    }
+
+    #[test]
+    fn render_scene_once_draws_scene() {
+        let mut scene = Scene::new();
+        scene.add(facing_triangle());
+        let mut canvas = Canvas::with_size(40, 40);
+        canvas.set(0, 0, Color::RED, 0.0);
+
+        canvas.render_scene_once(&scene);
+        let first = count_set(&canvas);
+        assert!(first > 0);
+        assert!(canvas.frame.pixels[0].is_none());
+
+        canvas.render_scene_once(&scene);
+        assert_eq!(count_set(&canvas), first);
+    }
+
+    #[test]
+    fn partial_bottom_row_is_drawn() {
+        let mut canvas = Canvas::with_size(4, 6);
+        canvas.clear();
+        canvas.set(1, 5, Color::RED, 0.0);
+
+        let (braille, _) = canvas.frame.cell(0, 1, None, None).unwrap();
+        assert_eq!(braille, '\u{2810}');
+        assert!(canvas.to_s().contains('\u{2810}'));
+    }
+
+    #[test]
+    fn unaligned_size_renders_without_panicking() {
+        // The last column and row of cells hang over the right and
+        // bottom edges, reading past them would run off the buffer
+        let mut canvas = Canvas::with_size(5, 7);
+        canvas.clear();
+        canvas.fill_rect(0, 0, 5, 7, Color::WHITE, 0.0);
+        let output = canvas.to_s();
+
+        // Full cells, then a cell with only its left column of dots
+        assert_eq!(canvas.frame.cell(1, 0, None, None).unwrap().0, '\u{28ff}');
+        assert_eq!(canvas.frame.cell(2, 0, None, None).unwrap().0, '\u{2847}');
+        assert_eq!(canvas.frame.cell(2, 1, None, None).unwrap().0, '\u{2807}');
+        assert!(output.contains('\u{2807}'));
+    }
+
+    #[test]
+    fn unproject_reverses_project_point() {
+        let mut canvas = Canvas::with_size(200, 200);
+        canvas.set_camera(Camera::from_yaw_pitch(Vec3f::new(1.0, 2.0, 3.0), 0.4, -0.2));
+        let camera = *canvas.get_camera();
+        let point = camera.position + camera.forward().scale(5.0) + camera.right().scale(0.7);
+
+        let (x, y, depth) = canvas.project_point(point).unwrap();
+        let back = canvas.unproject(x, y, depth);
+        // Off by at most the size of a pixel at that distance
+        assert!((back - point).length() < 0.1);
+
+        let behind = camera.position - camera.forward();
+        assert!(canvas.project_point(behind).is_none());
+    }
+
+    #[test]
+    fn highlight_where_light_reflects_to_camera() {
+        let brightness_facing = |direction: Vec3f| {
+            let mut entity = facing_triangle();
+            // Only the highlight is visible
+            entity.set_tint(Color::BLACK);
+            entity.set_specular(Color::WHITE, 8.0);
+            entity.set_direction(direction.x, direction.y, direction.z);
+            let mut canvas = Canvas::with_size(40, 40);
+            canvas.clear();
+            canvas.draw_entity(&entity);
+            canvas.frame.pixels.iter().flatten().map(|c| c.r as u32).max().unwrap_or(0)
+        };
+        // Halfway between the reversed light direction and the camera
+        let light = Vec3f::new(1.0, -1.0, -1.0).normalize();
+        let halfway = (Vec3f::new(0.0, 0.0, 1.0) - light).normalize();
+
+        assert!(brightness_facing(halfway) > 200);
+        assert!(brightness_facing(halfway) > brightness_facing(Vec3f::new(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn gouraud_shades_smoothly_across_triangles() {
+        let distinct_colors = |shading_mode: ShadingMode| {
+            let mut entity = Entity::with_geometry(shapes::make_icosphere(1.0, 0));
+            entity.set_translation(0.0, 0.0, -3.0);
+            let mut canvas = Canvas::with_size(80, 80);
+            canvas.set_shading_mode(shading_mode);
+            canvas.clear();
+            canvas.draw_entity(&entity);
+            let mut colors: Vec<(u8, u8, u8)> = canvas.frame.pixels.iter().flatten()
+                .map(|c| (c.r, c.g, c.b))
+                .collect();
+            colors.sort_unstable();
+            colors.dedup();
+            colors.len()
+        };
+        // At most one color per triangle when flat
+        assert!(distinct_colors(ShadingMode::Flat) <= 20);
+        assert!(distinct_colors(ShadingMode::Gouraud) > 40);
+    }
+
+    #[test]
+    fn narrower_fov_draws_larger() {
+        let mut canvas = Canvas::with_size(40, 40);
+        let entity = facing_triangle();
+        canvas.clear();
+        canvas.draw_entity(&entity);
+        let wide = count_set(&canvas);
+
+        canvas.set_projection(45.0, 0.1, 1000.0);
+        canvas.clear();
+        canvas.draw_entity(&entity);
+        assert!(count_set(&canvas) > wide);
+        assert_eq!(canvas.get_fov(), 45.0);
+    }
+
+    #[test]
+    fn unit_sphere_projects_round() {
+        let mut canvas = Canvas::with_size(80, 40);
+        let entity = Entity::with_geometry(shapes::make_icosphere(1.0, 3));
+        let center = Vec3f::new(0.0, 0.0, -4.0);
+        let (mut min_x, mut max_x, mut min_y, mut max_y) = (i32::MAX, i32::MIN, i32::MAX, i32::MIN);
+        for v in entity.shape.vertices() {
+            let (x, y, _) = canvas.project_point(v.position + center).unwrap();
+            min_x = min_x.min(x);
+            max_x = max_x.max(x);
+            min_y = min_y.min(y);
+            max_y = max_y.max(y);
+        }
+        let (w, h) = (max_x - min_x, max_y - min_y);
+        assert!((w - h).abs() <= 1, "{}x{}", w, h);
+
+        // Cells twice as wide make the same sphere half as wide in dots
+        canvas.set_cell_aspect(1.0);
+        let (x0, _, _) = canvas.project_point(center + Vec3f::new(-1.0, 0.0, 0.0)).unwrap();
+        let (x1, _, _) = canvas.project_point(center + Vec3f::new(1.0, 0.0, 0.0)).unwrap();
+        assert!(((x1 - x0) * 2 - w).abs() <= 2, "{} vs {}", x1 - x0, w);
+    }
+
+    #[test]
+    fn grid_lines_are_drawn_in_their_colors() {
+        let mut canvas = Canvas::with_size(40, 40);
+        canvas.set_camera(Camera::from_yaw_pitch(Vec3f::new(0.0, 3.0, 3.0), PI, -PI / 4.0));
+        canvas.clear();
+        canvas.draw_lines(&shapes::make_grid(2.0, 2));
+        assert!(canvas.frame.pixels.contains(&Some(Color::GRAY)));
+        assert!(canvas.frame.pixels.contains(&Some(Color::RED)));
+        assert!(canvas.frame.pixels.contains(&Some(Color::GREEN)));
+    }
+
+    #[test]
+    fn line_through_camera_is_clipped() {
+        let mut canvas = Canvas::with_size(40, 40);
+        canvas.clear();
+        canvas.draw_lines(&[(Vec3f::new(0.0, -1.0, 5.0), Vec3f::new(0.0, -1.0, -5.0), Color::RED)]);
+        let set: Vec<usize> = (0..40 * 40)
+            .filter(|&i| canvas.frame.pixels[i].is_some())
+            .collect();
+        assert!(!set.is_empty());
+        // The part in front of the camera runs from the bottom edge up
+        // towards the center, where the far end is
+        assert!(set.iter().any(|&i| i / 40 == 39));
+        assert!(set.iter().all(|&i| i / 40 > 20));
+    }
+
+    #[test]
+    fn clip_line_keeps_part_inside() {
+        let ((x0, y0), (x1, y1)) = clip_line((-1000.0, 5.0), (1000.0, 5.0), (39.0, 19.0)).unwrap();
+        assert!(x0.abs() < 1e-3 && (x1 - 39.0).abs() < 1e-3);
+        assert!((y0 - 5.0).abs() < 1e-3 && (y1 - 5.0).abs() < 1e-3);
+        assert_eq!(clip_line((-5.0, -5.0), (-1.0, 30.0), (39.0, 19.0)), None);
+    }
+
+    #[test]
+    fn out_of_range_triangle_is_skipped() {
+        let positions = vec![
+            Vec3f::new(-1.0, -1.0, 0.0),
+            Vec3f::new(1.0, -1.0, 0.0),
+            Vec3f::new(0.0, 1.0, 0.0),
+        ];
+        let normals = vec![Vec3f::new(0.0, 0.0, 1.0); 3];
+        let mut entity = Entity::with_geometry(Shape::with_normals(
+            positions, normals, vec![(0, 1, 7), (0, 1, 2)],
+        ));
+        entity.set_translation(0.0, 0.0, -3.0);
+
+        let mut canvas = Canvas::with_size(20, 20);
+        canvas.clear();
+        canvas.draw_entity(&entity);
+        assert!(count_set(&canvas) > 0);
+    }
+
+    #[test]
+    fn subdivided_triangle_fills_without_gaps() {
+        let mut entity = facing_triangle();
+        entity.set_translation(0.0, 0.0, -1.1);
+        let mut canvas = Canvas::with_size(60, 60);
+        canvas.set_subdivision_threshold(Some(4));
+        canvas.clear();
+        canvas.draw_entity(&entity);
+
+        // Every row is one unbroken span reaching the edges of the triangle
+        let mut rows = 0;
+        for y in 0..60 {
+            let row = &canvas.frame.pixels[y * 60..(y + 1) * 60];
+            let set: Vec<usize> = (0..60).filter(|x| row[*x].is_some()).collect();
+            if let (Some(first), Some(last)) = (set.first(), set.last()) {
+                assert_eq!(set.len(), last - first + 1, "gap in row {}", y);
+                rows += 1;
+            }
+        }
+        assert!(rows > 40);
+
+        // And it covers the same pixels as the triangle filled whole
+        let subdivided = count_set(&canvas);
+        canvas.set_subdivision_threshold(None);
+        canvas.clear();
+        canvas.draw_entity(&entity);
+        let whole = count_set(&canvas) as f32;
+        assert!((subdivided as f32 - whole).abs() / whole < 0.05);
+    }
+
+    #[test]
+    fn huge_subdivided_triangle_stops_splitting() {
+        let mut canvas = Canvas::with_size(20, 20);
+        canvas.clear();
+        let points = [
+            Vec3f::new(-1e7, -1e7, 0.0),
+            Vec3f::new(1e7, -1e7, 0.0),
+            Vec3f::new(0.0, 1e7, 0.0),
+        ];
+        canvas.fill_triangle_subdivided(points, 2, MAX_SUBDIVISION_LEVELS, &mut |_, _| Color::RED);
+        assert_eq!(count_set(&canvas), 20 * 20);
+    }
+
+    #[test]
+    fn depth_clear_value_is_what_set_compares_against() {
+        let mut canvas = Canvas::with_size(4, 4);
+        canvas.set_depth_func(DepthFunc::Greater);
+        canvas.set_depth_clear_value(-2.0);
+        canvas.clear();
+        canvas.set(1, 1, Color::RED, -1.5);
+        assert_eq!(canvas.frame.pixels[4 + 1], Some(Color::RED));
+
+        canvas.set_depth_clear_value(0.5);
+        canvas.clear();
+        canvas.set(1, 1, Color::RED, 0.0);
+        assert_eq!(canvas.frame.pixels[4 + 1], None);
+    }
+
+    #[test]
+    fn frame_stats_count_triangles() {
+        let mut canvas = Canvas::with_size(40, 40);
+        let mut sphere = Entity::with_geometry(shapes::make_icosphere(1.0, 1));
+        sphere.set_translation(0.0, 0.0, -4.0);
+        canvas.clear();
+        canvas.draw_entity(&sphere);
+        let stats = canvas.last_frame_stats();
+        assert_eq!(stats.triangles_submitted, sphere.shape.triangle_count() as u64);
+        // About half of a closed mesh faces away from the camera
+        assert!(stats.triangles_culled > 0 && stats.triangles_culled < stats.triangles_submitted);
+        assert!(stats.pixels_set > 0);
+
+        // Drawing the same sphere again is hidden by the first one
+        canvas.set_depth_func(DepthFunc::Greater);
+        canvas.draw_entity(&sphere);
+        assert!(canvas.last_frame_stats().pixels_depth_rejected > 0);
+
+        canvas.clear();
+        assert_eq!(canvas.last_frame_stats(), FrameStats::default());
+    }
+
+    #[test]
+    fn dark_entities_stay_visible() {
+        let mut canvas = Canvas::with_size(20, 20);
+        let mut entity = facing_triangle();
+        entity.set_tint(Color::new(4, 0, 0));
+        canvas.set_min_visible(Some(40));
+        canvas.clear();
+        canvas.draw_entity(&entity);
+        assert!(canvas.frame.pixels.iter().flatten().count() > 0);
+        assert!(canvas.frame.pixels.iter().flatten().all(|c| c.r >= 40 && c.g == 0));
+    }
+
+    #[test]
+    fn rolled_camera_turns_up_sideways() {
+        let mut camera = Camera::default();
+        camera.rotate_roll(PI / 2.0);
+        // Looking down -Z, a counter-clockwise quarter turn tilts up to -X
+        assert!((camera.up() - Vec3f::new(-1.0, 0.0, 0.0)).length() < 1e-5);
+        assert!((camera.right() - Vec3f::new(0.0, 1.0, 0.0)).length() < 1e-5);
+        camera.rotate(0.1, 0.0);
+        assert_eq!(camera.roll, PI / 2.0);
+    }
+
+    #[test]
+    fn write_to_buffer_contains_braille() {
+        let mut canvas = Canvas::with_size(2, 4);
+        canvas.set(0, 0, Color::WHITE, 0.0);
+        let mut buffer: Vec<u8> = Vec::new();
+        canvas.write_to(&mut buffer).unwrap();
+        let output = String::from_utf8(buffer).unwrap();
+        assert!(output.contains('\u{2801}'));
+        assert_eq!(output, canvas.to_s());
+    }
+
+    #[test]
+    fn resize_keeps_shapes_round() {
+        let mut canvas = Canvas::with_size(20, 20);
+        canvas.set_sample_factor(2);
+        canvas.resize(40, 20);
+        assert_eq!(canvas.size(), (80, 40));
+        assert_eq!(canvas.frame.pixels.len(), 80 * 40);
+        assert!((canvas.aspect_ratio() - 2.0).abs() < 1e-6);
+
+        // Drawing after resizing stays inside the new buffers
+        canvas.draw_entity(&facing_triangle());
+        assert!(count_set(&canvas) > 0);
+    }
+
+    #[test]
+    fn fill_spans_stay_inside_triangle() {
+        // The middle vertex far to the left of the long edge, and
+        // straddling it
+        for &points in &[[(30, 0), (0, 12), (31, 39)], [(18, 38), (30, 9), (26, 19)]] {
+            let mut canvas = Canvas::with_size(40, 40);
+            canvas.clear();
+            let [(x0, y0), (x1, y1), (x2, y2)] = points;
+            canvas.fill_triangle(x0, y0, x1, y1, x2, y2, Color::WHITE, 0.0);
+
+            // How many pixels a pixel is outside the furthest edge
+            let outside = |x: i32, y: i32| points.iter().enumerate().map(|(i, &(ax, ay))| {
+                let (bx, by) = points[(i + 1) % 3];
+                let (cx, cy) = points[(i + 2) % 3];
+                let (ex, ey) = ((bx - ax) as f32, (by - ay) as f32);
+                let side = |px: i32, py: i32| (px - ax) as f32 * ey - (py - ay) as f32 * ex;
+                -side(x, y) * side(cx, cy).signum() / (ex * ex + ey * ey).sqrt()
+            }).fold(f32::MIN, f32::max);
+            for y in 0..40 {
+                let row = &canvas.frame.pixels[y * 40..(y + 1) * 40];
+                for (x, pixel) in row.iter().enumerate() {
+                    if pixel.is_some() {
+                        assert!(outside(x as i32, y as i32) <= 1.0, "({}, {}) spills", x, y);
+                    }
+                }
+                // Every row the triangle spans has pixels
+                let (min_y, max_y) = (y0.min(y1).min(y2), y0.max(y1).max(y2));
+                if (min_y..=max_y).contains(&(y as i32)) {
+                    assert!(row.iter().any(|p| p.is_some()), "row {} is empty", y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blit_fills_rect_with_texture() {
+        let mut canvas = Canvas::with_size(20, 20);
+        canvas.clear();
+        let texture = Texture::new(vec![Color::GREEN; 4], 2, 2);
+        canvas.blit_texture(&texture, Some((3, 4, 10, 5)));
+        for y in 0..20 {
+            for x in 0..20 {
+                let inside = (3..13).contains(&x) && (4..9).contains(&y);
+                let expected = if inside { Some(Color::GREEN) } else { None };
+                assert_eq!(canvas.frame.pixels[y * 20 + x], expected);
+            }
+        }
+
+        // Corners of the texture land on the corners of the canvas
+        let texture = Texture::new(vec![Color::RED, Color::GREEN, Color::BLUE, Color::WHITE], 2, 2);
+        canvas.blit_texture(&texture, None);
+        assert_eq!(canvas.frame.pixels[0], Some(Color::RED));
+        assert_eq!(canvas.frame.pixels[19], Some(Color::GREEN));
+        assert_eq!(canvas.frame.pixels[19 * 20], Some(Color::BLUE));
+        assert_eq!(canvas.frame.pixels[20 * 20 - 1], Some(Color::WHITE));
+
+        // Drawing over the background works with the default depth test
+        canvas.set(0, 0, Color::BLACK, 0.0);
+        assert_eq!(canvas.frame.pixels[0], Some(Color::BLACK));
+    }
+
+    #[test]
+    fn repeating_texture_tiles_across_quad() {
+        // A quad of thin vertical strips with u going from 0 to 4, as
+        // textures are sampled once per triangle
+        let strips = 16;
+        let mut positions = Vec::new();
+        let mut texcoords = Vec::new();
+        for i in 0..=strips {
+            let t = i as f32 / strips as f32;
+            positions.push(Vec3f::new(t * 2.0 - 1.0, -1.0, 0.0));
+            positions.push(Vec3f::new(t * 2.0 - 1.0, 1.0, 0.0));
+            texcoords.push((t * 4.0, 1.0));
+            texcoords.push((t * 4.0, 0.0));
+        }
+        let triangles = (0..strips).flat_map(|i| {
+            let (b0, t0, b1, t1) = (2 * i, 2 * i + 1, 2 * i + 2, 2 * i + 3);
+            vec![(b0, b1, t1), (b0, t1, t0)]
+        }).collect();
+        let mut entity = Entity::with_geometry(Shape::with_texcoords(positions, triangles, texcoords));
+        entity.set_translation(0.0, 0.0, -3.0);
+        let mut texture = Texture::new(vec![Color::RED, Color::BLUE], 2, 1);
+        texture.set_wrap_mode(WrapMode::Repeat);
+        entity.set_texture(TextureSlot::Diffuse, texture);
+
+        let mut canvas = Canvas::with_size(64, 32);
+        canvas.clear();
+        canvas.draw_entity(&entity);
+
+        // Every tile is red on the left and blue on the right
+        let row = &canvas.frame.pixels[16 * 64..17 * 64];
+        let mut red_runs = 0;
+        let mut was_red = false;
+        for color in row.iter().flatten() {
+            let is_red = color.r > color.b;
+            if is_red && !was_red {
+                red_runs += 1;
+            }
+            was_red = is_red;
+        }
+        assert_eq!(red_runs, 4);
+    }
+
+    #[test]
+    fn rotating_entity_half_a_turn_swaps_lit_faces() {
+        // A ridge pointing at the camera, the upper face tilted up
+        // towards the light and the lower one tilted down away from it
+        let mut entity = Entity::with_geometry(Shape::with_tris(
+            vec![
+                Vec3f::new(-1.0, 0.0, 0.0),
+                Vec3f::new(1.0, 0.0, 0.0),
+                Vec3f::new(0.0, 1.0, -1.0),
+                Vec3f::new(0.0, -1.0, -1.0),
+            ],
+            vec![(0, 1, 2), (0, 3, 1)],
+        ));
+        entity.set_translation(0.0, 0.0, -3.0);
+        let brightness_at = |entity: &Entity, y: usize| {
+            let mut canvas = Canvas::with_size(32, 32);
+            canvas.clear();
+            canvas.draw_entity(entity);
+            canvas.frame.pixels[y * 32 + 16].unwrap().r
+        };
+        let (upper, lower) = (brightness_at(&entity, 14), brightness_at(&entity, 18));
+        assert!(upper > lower);
+
+        // Half a turn around the view axis swaps the faces, and the one
+        // now on top is the one facing the light
+        entity.set_rotation_euler(0.0, 0.0, PI);
+        assert_eq!(brightness_at(&entity, 14), upper);
+        assert_eq!(brightness_at(&entity, 18), lower);
+    }
+
+    #[test]
+    fn view_space_light_follows_camera() {
+        let mut canvas = Canvas::with_size(32, 32);
+        let world = Vec3f::new(1.0, -1.0, -1.0).normalize();
+        assert!((canvas.light_direction() - world).length() < 1e-5);
+        canvas.set_light_space(LightSpace::View);
+        canvas.set_camera(Camera::from_yaw_pitch(Vec3f::new(0.0, 0.0, 0.0), 0.0, 0.0));
+        // Turned half a turn, the light still comes from over the
+        // camera's left shoulder
+        let turned = Vec3f::new(-1.0, -1.0, 1.0).normalize();
+        assert!((canvas.light_direction() - turned).length() < 1e-5);
+    }
+
+    #[test]
+    fn depth_is_linear_in_distance() {
+        let depth_at = |distance: f32| {
+            let mut entity = facing_triangle();
+            entity.set_translation(0.0, 0.0, -distance);
+            entity.set_scale(distance);
+            let mut canvas = Canvas::with_size(32, 32);
+            canvas.clear();
+            canvas.draw_entity(&entity);
+            (canvas.frame.depth[16 * 32 + 16], canvas)
+        };
+        let (near, canvas) = depth_at(3.0);
+        let (middle, _) = depth_at(500.05);
+        let (far, _) = depth_at(900.0);
+        assert!(near > middle && middle > far);
+        assert!((near - canvas.normalize_depth(3.0)).abs() < 1e-5);
+        // Halfway between the near and far planes is halfway through
+        // the depth range
+        assert!(middle.abs() < 1e-4);
+        assert!((canvas.depth_to_distance(far) - 900.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn aabb_edges_meet_at_projected_corners() {
+        let mut canvas = Canvas::with_size(64, 64);
+        canvas.clear();
+        let (min, max) = (Vec3f::new(-1.0, -1.0, -6.0), Vec3f::new(1.0, 1.0, -4.0));
+        canvas.draw_aabb(min, max, Color::GREEN);
+
+        for &x in &[min.x, max.x] {
+            for &y in &[min.y, max.y] {
+                for &z in &[min.z, max.z] {
+                    let (px, py, _) = canvas.project_point(Vec3f::new(x, y, z)).unwrap();
+                    assert_eq!(canvas.frame.pixels[py as usize * 64 + px as usize], Some(Color::GREEN));
+                }
+            }
+        }
+        // The middle of the front face is empty
+        assert_eq!(canvas.frame.pixels[32 * 64 + 32], None);
+
+        // Boxes around the camera only draw the edges in front of it
+        canvas.clear();
+        canvas.draw_aabb(Vec3f::new(-1.0, -1.0, -5.0), Vec3f::new(1.0, 1.0, 5.0), Color::GREEN);
+        assert!(count_set(&canvas) > 0);
+    }
+
+    #[test]
+    fn aabb_corner_at_camera_is_clipped() {
+        // The near corners project almost infinitely far off screen
+        let mut canvas = Canvas::with_size(64, 64);
+        canvas.clear();
+        canvas.draw_aabb(Vec3f::new(-1.0, -1.0, -5.0), Vec3f::new(1.0, 1.0, -1e-6), Color::GREEN);
+        let set = count_set(&canvas);
+        assert!(set > 0 && set < 64 * 64);
+        // The far face is drawn where it projects
+        let (px, py, _) = canvas.project_point(Vec3f::new(1.0, 1.0, -5.0)).unwrap();
+        assert_eq!(canvas.frame.pixels[py as usize * 64 + px as usize], Some(Color::GREEN));
+    }
+}
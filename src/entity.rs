@@ -1,23 +1,44 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path, rc::Rc};
 
-use crate::{math::{Mat4x4f, Vec3f}, shapes::Shape, texture::{Color, Texture}};
+use crate::{math::{Mat4x4f, Quat, Vec3f}, shapes::Shape, texture::{Color, Texture, TextureSlot, WrapMode}};
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Entity {
-    pub shape: Shape,
+    pub shape: Rc<Shape>,
     translation: Vec3f,
-    direction: Vec3f,
-    scale: f32,
-    texture: Option<Texture>,
+    rotation: Quat,
+    scale: Vec3f,
+    origin: Vec3f,
+    textures: HashMap<TextureSlot, Texture>,
+    tint: Color,
+    specular: Color,
+    shininess: f32,
+    visible: bool,
+    billboard: bool,
 }
 
 impl Entity {
     pub fn with_geometry(shape: Shape) -> Self {
+        Self::with_geometry_shared(Rc::new(shape))
+    }
+
+    /// Creates an entity drawing a shape that may be shared with other
+    /// entities, so that instances of the same mesh don't each hold a
+    /// copy of its vertices. Cloning an entity shares its shape as well.
+    pub fn with_geometry_shared(shape: Rc<Shape>) -> Self {
         Self {
             shape,
             translation: Vec3f::zero(),
-            direction: Vec3f::new(0.0, 0.0, 1.0),
-            scale: 1.0,
-            texture: None,
+            rotation: Quat::identity(),
+            scale: Vec3f::new(1.0, 1.0, 1.0),
+            origin: Vec3f::zero(),
+            textures: HashMap::new(),
+            tint: Color::WHITE,
+            specular: Color::BLACK,
+            shininess: 32.0,
+            visible: true,
+            billboard: false,
         }
     }
 
@@ -33,53 +54,547 @@ impl Entity {
         self.translation
     }
 
+    /// Sets the point in the entity's local space that it rotates and
+    /// scales around, such as the hinge of a door. Defaults to the local
+    /// origin. Changing it doesn't move the mesh until it's rotated or
+    /// scaled.
+    pub fn set_origin(&mut self, offset: Vec3f) {
+        self.origin = offset;
+    }
+
+    /// Returns the point the entity rotates and scales around, in its
+    /// local space
+    pub fn get_origin(&self) -> Vec3f {
+        self.origin
+    }
+
+    /// Rotates the entity so that its local +Z axis points along (x, y, z)
     pub fn set_direction(&mut self, x: f32, y: f32, z: f32) {
-        self.direction = Vec3f::new(x, y, z).normalize();
+        self.rotation = Quat::look_rotation(Vec3f::new(x, y, z));
     }
 
+    /// Returns the direction the entity's local +Z axis points in
     pub fn get_direction(&self) -> Vec3f {
-        self.direction
+        self.rotation.rotate(&Vec3f::new(0.0, 0.0, 1.0))
+    }
+
+    /// Rotates the entity by Euler angles in radians. The entity is first
+    /// pitched around X, then yawed around Y and last rolled around Z,
+    /// like the matrix `rotate_z(roll) * rotate_y(yaw) * rotate_x(pitch)`.
+    /// Every rotation is counter-clockwise looking down its axis.
+    pub fn set_rotation_euler(&mut self, pitch: f32, yaw: f32, roll: f32) {
+        let x = Quat::from_axis_angle(Vec3f::new(1.0, 0.0, 0.0), pitch);
+        let y = Quat::from_axis_angle(Vec3f::new(0.0, 1.0, 0.0), yaw);
+        let z = Quat::from_axis_angle(Vec3f::new(0.0, 0.0, 1.0), roll);
+        self.rotation = (z * y * x).normalize();
+    }
+
+    pub fn set_rotation(&mut self, rotation: Quat) {
+        self.rotation = rotation.normalize();
+    }
+
+    pub fn get_rotation(&self) -> Quat {
+        self.rotation
+    }
+
+    /// Rotates the entity `angle` radians around `axis`, on top of its
+    /// current rotation
+    pub fn rotate_axis_angle(&mut self, axis: Vec3f, angle: f32) {
+        self.rotation = (Quat::from_axis_angle(axis, angle) * self.rotation).normalize();
+    }
+
+    /// Turns the entity `angle` radians around the world's Y axis, on top
+    /// of its current rotation
+    pub fn rotate_yaw(&mut self, angle: f32) {
+        self.rotate_axis_angle(Vec3f::new(0.0, 1.0, 0.0), angle);
+    }
+
+    /// Tilts the entity `angle` radians around its own X axis
+    pub fn rotate_pitch(&mut self, angle: f32) {
+        self.rotate_local(Vec3f::new(1.0, 0.0, 0.0), angle);
+    }
+
+    /// Rolls the entity `angle` radians around its own Z axis
+    pub fn rotate_roll(&mut self, angle: f32) {
+        self.rotate_local(Vec3f::new(0.0, 0.0, 1.0), angle);
+    }
+
+    fn rotate_local(&mut self, axis: Vec3f, angle: f32) {
+        self.rotation = (self.rotation * Quat::from_axis_angle(axis, angle)).normalize();
     }
 
+    /// Scales the entity uniformly by `scale` along all axes
     pub fn set_scale(&mut self, scale: f32) {
-        self.scale = scale;
+        self.scale = Vec3f::new(scale, scale, scale);
+    }
+
+    /// Scales the entity by a separate factor along each local axis
+    pub fn set_scale_xyz(&mut self, x: f32, y: f32, z: f32) {
+        self.scale = Vec3f::new(x, y, z);
+    }
+
+    pub fn get_scale(&self) -> Vec3f {
+        self.scale
     }
 
     pub fn scale(&mut self, scale: f32) {
-        self.scale *= scale;
+        self.scale = self.scale.scale(scale);
     }
 
-    pub fn load_texture<P: AsRef<Path>>(&mut self, filepath: P) {
-        self.texture = Some(Texture::load_from_file(filepath));
+    /// Sets a color multiplied into the entity's texture (or white, if
+    /// it has none) when drawn
+    pub fn set_tint(&mut self, tint: Color) {
+        self.tint = tint;
     }
 
-    /// Samples the entities texture using the texcoords in the
-    /// interval [0, 1]. Returns Color(255, 255, 255) if entity
-    /// has no texture (if load_texture hasn't been called).
-    pub fn sample_texture(&self, (u, v): (f32, f32)) -> Color {
-        if let Some(tex) = &self.texture {
-            tex.sample(u, v)
-        } else {
-            // Returns white if there is no texture
-            Color::WHITE
+    pub fn get_tint(&self) -> Color {
+        self.tint
+    }
+
+    /// Sets the color of the highlights where light reflects towards the
+    /// camera. Higher `shininess` gives smaller, sharper highlights.
+    /// Black (the default) disables highlights.
+    pub fn set_specular(&mut self, specular: Color, shininess: f32) {
+        self.specular = specular;
+        self.shininess = shininess;
+    }
+
+    /// Returns the specular color and shininess
+    pub fn get_specular(&self) -> (Color, f32) {
+        (self.specular, self.shininess)
+    }
+
+    /// Hides or shows the entity. Hidden entities are skipped when drawn.
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Loads a texture into the diffuse slot, wrapping texcoords by
+    /// `wrap_mode` if given, see `Texture::set_wrap_mode`
+    pub fn load_texture<P: AsRef<Path>>(&mut self, filepath: P, wrap_mode: Option<WrapMode>) {
+        let mut texture = Texture::load_from_file(filepath);
+        if let Some(wrap_mode) = wrap_mode {
+            texture.set_wrap_mode(wrap_mode);
         }
+        self.set_texture(TextureSlot::Diffuse, texture);
+    }
+
+    pub fn set_texture(&mut self, slot: TextureSlot, texture: Texture) {
+        self.textures.insert(slot, texture);
+    }
+
+    pub fn get_texture(&self, slot: TextureSlot) -> Option<&Texture> {
+        self.textures.get(&slot)
+    }
+
+    /// Samples the texture in `slot` using the texcoords, wrapped by the
+    /// texture's wrap mode, or returns None if the slot is empty.
+    pub fn sample(&self, slot: TextureSlot, (u, v): (f32, f32)) -> Option<Color> {
+        self.get_texture(slot).map(|tex| tex.sample(u, v))
+    }
+
+    /// Samples the entities diffuse texture using the texcoords, wrapped
+    /// by the texture's wrap mode. Returns Color(255, 255, 255) if entity
+    /// has no texture (if load_texture hasn't been called).
+    pub fn sample_texture(&self, uv: (f32, f32)) -> Color {
+        // Returns white if there is no texture
+        self.sample(TextureSlot::Diffuse, uv).unwrap_or(Color::WHITE)
     }
 
     pub fn gen_local_transform(&self) -> Mat4x4f {
-        // We are assuming that the direction vector is normalized
-        let theta = if self.direction.x >= 0.0 {
-            self.direction.z.acos()
-        } else {
-            -self.direction.z.acos() // TODO fix this shit
-        };
-        let phi = -self.direction.y.asin();
-        let s = Mat4x4f::identity() * self.scale;
-        let ry = Mat4x4f::rotate_y(theta);
-        let rx = Mat4x4f::rotate_x(phi);
-        let mut transform = ry * rx * s;
-        transform.m[0][3] = self.translation.x;
-        transform.m[1][3] = self.translation.y;
-        transform.m[2][3] = self.translation.z;
+        self.gen_transform(self.rotation)
+    }
+
+    /// Generates the matrix transforming normals into the same space as
+    /// `gen_local_transform` does positions. This is the inverse transpose
+    /// of the local transform, which differs from it when the scale isn't
+    /// uniform. Transformed normals need to be normalized again.
+    pub fn gen_normal_transform(&self) -> Mat4x4f {
+        self.gen_normal_transform_with(self.rotation)
+    }
+
+    /// Generates the inverse of `gen_local_transform`, transforming
+    /// world space into the entity's local space
+    pub fn gen_inverse_transform(&self) -> Mat4x4f {
+        let s = Mat4x4f::scaling(1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z);
+        let r = self.rotation.conjugate().to_matrix();
+        let pivot = self.translation + self.origin;
+        let t = Mat4x4f::translation(-pivot.x, -pivot.y, -pivot.z);
+        let o = Mat4x4f::translation(self.origin.x, self.origin.y, self.origin.z);
+        o * s * r * t
+    }
+
+    /// Transforms a point in the entity's local space into world space
+    pub fn transform_point(&self, local: Vec3f) -> Vec3f {
+        self.gen_local_transform().vecmul(&local, true)
+    }
+
+    /// Transforms a point in world space into the entity's local space,
+    /// reversing `transform_point`
+    pub fn inverse_transform_point(&self, world: Vec3f) -> Vec3f {
+        self.gen_inverse_transform().vecmul(&world, true)
+    }
+
+    /// Returns the axis-aligned box enclosing the entity in world space,
+    /// as its (min, max) corners. The box encloses the transformed
+    /// corners of the shape's bounding box, so it may be loose.
+    pub fn world_bounding_box(&self) -> (Vec3f, Vec3f) {
+        self.bounding_box_with(&self.gen_local_transform())
+    }
+
+    /// Returns the axis-aligned box enclosing the shape after
+    /// transforming it with `lt`
+    pub(crate) fn bounding_box_with(&self, lt: &Mat4x4f) -> (Vec3f, Vec3f) {
+        let (min, max) = self.shape.bounding_box();
+        let mut world_min = Vec3f::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut world_max = Vec3f::new(f32::MIN, f32::MIN, f32::MIN);
+        for i in 0..8 {
+            let corner = Vec3f::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+            let p = lt.vecmul(&corner, true);
+            world_min = Vec3f::new(world_min.x.min(p.x), world_min.y.min(p.y), world_min.z.min(p.z));
+            world_max = Vec3f::new(world_max.x.max(p.x), world_max.y.max(p.y), world_max.z.max(p.z));
+        }
+        (world_min, world_max)
+    }
+
+    /// Finds the nearest triangle hit by the ray starting at `origin`
+    /// going in `direction`, both in world space. Both sides of the
+    /// triangles can be hit.
+    pub fn raycast(&self, origin: Vec3f, direction: Vec3f) -> Option<RayHit> {
+        let direction = direction.normalize();
+        let it = self.gen_inverse_transform();
+        let local_origin = it.vecmul(&origin, true);
+        let local_direction = it.vecmul(&direction, false);
+        let lt = self.gen_local_transform();
+
+        let mut nearest: Option<RayHit> = None;
+        for (triangle_index, tri) in self.shape.triangles().enumerate() {
+            let p0 = self.shape.get(tri.0).position;
+            let p1 = self.shape.get(tri.1).position;
+            let p2 = self.shape.get(tri.2).position;
+            let t = match intersect_triangle(local_origin, local_direction, p0, p1, p2) {
+                Some(t) => t,
+                None => continue,
+            };
+            // Distances are measured in world space since the local
+            // transform may scale the ray
+            let point = lt.vecmul(&(local_origin + local_direction.scale(t)), true);
+            let distance = (point - origin).length();
+            if nearest.as_ref().is_none_or(|hit| distance < hit.distance) {
+                nearest = Some(RayHit { distance, triangle_index, point });
+            }
+        }
+        nearest
+    }
+
+    /// Makes the entity ignore its rotation and always turn its local +Z
+    /// axis towards the camera when drawn. Useful for sprites and labels.
+    pub fn set_billboard(&mut self, billboard: bool) {
+        self.billboard = billboard;
+    }
+
+    pub fn is_billboard(&self) -> bool {
+        self.billboard
+    }
+
+    /// Like `gen_local_transform`, but rotated so that the local +Z axis
+    /// points towards `camera_position`
+    pub fn gen_billboard_transform(&self, camera_position: Vec3f) -> Mat4x4f {
+        self.gen_transform(self.rotation_facing(camera_position))
+    }
+
+    /// Like `gen_normal_transform`, but for `gen_billboard_transform`
+    pub fn gen_billboard_normal_transform(&self, camera_position: Vec3f) -> Mat4x4f {
+        self.gen_normal_transform_with(self.rotation_facing(camera_position))
+    }
+
+    /// Returns the rotation turning the entity's local +Z axis from its
+    /// pivot, see `set_origin`, towards `camera_position`
+    fn rotation_facing(&self, camera_position: Vec3f) -> Quat {
+        Quat::look_rotation(camera_position - (self.translation + self.origin))
+    }
+
+    fn gen_transform(&self, rotation: Quat) -> Mat4x4f {
+        let s = Mat4x4f::scaling(self.scale.x, self.scale.y, self.scale.z);
+        let mut transform = rotation.to_matrix() * s;
+        // Rotate and scale around the origin, i.e. move it to (0, 0, 0)
+        // first and back afterwards
+        let rotated_origin = transform.vecmul(&self.origin, false);
+        let offset = self.translation + self.origin - rotated_origin;
+        transform.m[0][3] = offset.x;
+        transform.m[1][3] = offset.y;
+        transform.m[2][3] = offset.z;
         transform
     }
+
+    fn gen_normal_transform_with(&self, rotation: Quat) -> Mat4x4f {
+        let s = Mat4x4f::scaling(1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z);
+        rotation.to_matrix() * s
+    }
+}
+
+/// Where a ray hit an entity
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    /// The distance from the ray's origin to the hit, in world space
+    pub distance: f32,
+    /// The index of the hit triangle in the entity's shape
+    pub triangle_index: usize,
+    /// The world space position of the hit
+    pub point: Vec3f,
+}
+
+/// Intersects a ray with a triangle using the Möller–Trumbore algorithm.
+/// Returns how many `direction`s along the ray the hit is.
+fn intersect_triangle(origin: Vec3f, direction: Vec3f, p0: Vec3f, p1: Vec3f, p2: Vec3f) -> Option<f32> {
+    const EPSILON: f32 = 1e-7;
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let h = direction.cross(&edge2);
+    let a = edge1.dot(&h);
+    if a.abs() < EPSILON {
+        // The ray is parallel to the triangle
+        return None;
+    }
+    let f = 1.0 / a;
+    let s = origin - p0;
+    let u = f * s.dot(&h);
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(&edge1);
+    let v = f * direction.dot(&q);
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = f * edge2.dot(&q);
+    if t > EPSILON { Some(t) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::shapes::{self, Shape};
+
+    fn assert_near(a: Vec3f, b: Vec3f) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn two_quarter_turns_make_a_half_turn() {
+        let y_axis = Vec3f::new(0.0, 1.0, 0.0);
+        let mut twice = Entity::with_geometry(shapes::make_icosphere(1.0, 0));
+        twice.rotate_axis_angle(y_axis, PI / 2.0);
+        twice.rotate_axis_angle(y_axis, PI / 2.0);
+        let mut once = Entity::with_geometry(shapes::make_icosphere(1.0, 0));
+        once.set_rotation(Quat::from_axis_angle(y_axis, PI));
+
+        let p = Vec3f::new(1.0, 2.0, 3.0);
+        assert_near(
+            twice.gen_local_transform().vecmul(&p, true),
+            once.gen_local_transform().vecmul(&p, true),
+        );
+        assert_near(once.gen_local_transform().vecmul(&p, true), Vec3f::new(-1.0, 2.0, -3.0));
+    }
+
+    #[test]
+    fn non_uniform_scale_stretches_one_axis() {
+        let corners: Vec<Vec3f> = (0..8).map(|i| Vec3f::new(
+            if i & 1 == 0 { -1.0 } else { 1.0 },
+            if i & 2 == 0 { -1.0 } else { 1.0 },
+            if i & 4 == 0 { -1.0 } else { 1.0 },
+        )).collect();
+        let cube = Shape::with_tris(corners.clone(), vec![(0, 1, 3), (0, 3, 2)]);
+        let mut entity = Entity::with_geometry(cube);
+        entity.set_scale_xyz(2.0, 1.0, 1.0);
+
+        let lt = entity.gen_local_transform();
+        let transformed: Vec<Vec3f> = corners.iter().map(|c| lt.vecmul(c, true)).collect();
+        let extent = |axis: fn(&Vec3f) -> f32| {
+            let max = transformed.iter().map(axis).fold(f32::MIN, f32::max);
+            let min = transformed.iter().map(axis).fold(f32::MAX, f32::min);
+            max - min
+        };
+        assert!((extent(|v| v.x) - 4.0).abs() < 1e-5);
+        assert!((extent(|v| v.y) - 2.0).abs() < 1e-5);
+        assert!((extent(|v| v.z) - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn set_direction_points_local_z() {
+        let mut entity = Entity::with_geometry(shapes::make_icosphere(1.0, 0));
+        entity.set_direction(1.0, 1.0, 0.0);
+        assert_near(entity.get_direction(), Vec3f::new(1.0, 1.0, 0.0).normalize());
+    }
+
+    #[test]
+    fn billboard_faces_camera() {
+        let quad = Shape::with_tris(
+            vec![
+                Vec3f::new(-1.0, -1.0, 0.0),
+                Vec3f::new(1.0, -1.0, 0.0),
+                Vec3f::new(1.0, 1.0, 0.0),
+                Vec3f::new(-1.0, 1.0, 0.0),
+            ],
+            vec![(0, 1, 2), (0, 2, 3)],
+        );
+        let normal = quad.get(0).normal;
+        let mut entity = Entity::with_geometry(quad);
+        entity.set_translation(1.0, 2.0, -10.0);
+        entity.set_billboard(true);
+
+        for camera in [Vec3f::zero(), Vec3f::new(-20.0, 5.0, 0.0), Vec3f::new(3.0, -4.0, -25.0)] {
+            let nt = entity.gen_billboard_normal_transform(camera);
+            let to_camera = (camera - entity.get_translation()).normalize();
+            assert_near(nt.vecmul(&normal, false).normalize(), to_camera);
+        }
+
+        // With the pivot moved, the entity faces the camera from the pivot
+        entity.set_origin(Vec3f::new(4.0, 0.0, 0.0));
+        let pivot = entity.gen_billboard_transform(Vec3f::zero()).vecmul(&entity.get_origin(), true);
+        assert_near(pivot, Vec3f::new(5.0, 2.0, -10.0));
+        let nt = entity.gen_billboard_normal_transform(Vec3f::zero());
+        assert_near(nt.vecmul(&normal, false).normalize(), (-pivot).normalize());
+    }
+
+    #[test]
+    fn raycast_hits_triangle() {
+        let shape = Shape::with_tris(
+            vec![
+                Vec3f::new(-1.0, -1.0, 0.0),
+                Vec3f::new(1.0, -1.0, 0.0),
+                Vec3f::new(0.0, 1.0, 0.0),
+            ],
+            vec![(0, 1, 2)],
+        );
+        let mut entity = Entity::with_geometry(shape);
+        entity.set_translation(0.0, 0.0, -5.0);
+        entity.set_scale(2.0);
+
+        let hit = entity.raycast(Vec3f::new(0.5, 0.0, 0.0), Vec3f::new(0.0, 0.0, -1.0)).unwrap();
+        assert_near(hit.point, Vec3f::new(0.5, 0.0, -5.0));
+        assert!((hit.distance - 5.0).abs() < 1e-4);
+        assert_eq!(hit.triangle_index, 0);
+
+        assert!(entity.raycast(Vec3f::new(5.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, -1.0)).is_none());
+        assert!(entity.raycast(Vec3f::zero(), Vec3f::new(0.0, 0.0, 1.0)).is_none());
+    }
+
+    #[test]
+    fn bounding_box_follows_translation() {
+        let mut entity = Entity::with_geometry(shapes::make_uv_sphere(1.0, 8, 8));
+        let (min, max) = entity.world_bounding_box();
+        entity.set_translation(3.0, -2.0, 10.0);
+        let (moved_min, moved_max) = entity.world_bounding_box();
+
+        let offset = Vec3f::new(3.0, -2.0, 10.0);
+        assert_near(moved_min, min + offset);
+        assert_near(moved_max, max + offset);
+        assert!((max.y - min.y - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn clone_moves_independently() {
+        let mut entity = Entity::with_geometry(shapes::make_uv_sphere(1.0, 8, 8));
+        entity.set_translation(1.0, 2.0, 3.0);
+        let mut copy = entity.clone();
+        copy.translate(5.0, 0.0, 0.0);
+
+        assert_near(entity.get_translation(), Vec3f::new(1.0, 2.0, 3.0));
+        assert_near(copy.get_translation(), Vec3f::new(6.0, 2.0, 3.0));
+        assert_eq!(copy.shape.triangle_count(), entity.shape.triangle_count());
+    }
+
+    #[test]
+    fn texture_slots_sample_independently() {
+        let mut entity = Entity::with_geometry(shapes::make_icosphere(1.0, 0));
+        entity.set_texture(TextureSlot::Diffuse, Texture::new(vec![Color::RED; 4], 2, 2));
+        entity.set_texture(TextureSlot::Emissive, Texture::new(vec![Color::BLUE; 4], 2, 2));
+
+        let diffuse = entity.sample(TextureSlot::Diffuse, (0.5, 0.5)).unwrap();
+        let emissive = entity.sample(TextureSlot::Emissive, (0.5, 0.5)).unwrap();
+        assert_eq!((diffuse.r, diffuse.g, diffuse.b), (255, 0, 0));
+        assert_eq!((emissive.r, emissive.g, emissive.b), (0, 0, 255));
+        assert!(entity.sample(TextureSlot::Normal, (0.5, 0.5)).is_none());
+
+        let sampled = entity.sample_texture((0.5, 0.5));
+        assert_eq!((sampled.r, sampled.g, sampled.b), (255, 0, 0));
+    }
+
+    #[test]
+    fn yaw_turns_forward_towards_x() {
+        let mut entity = Entity::with_geometry(shapes::make_quad(1.0, 1.0, 0));
+        entity.set_rotation_euler(0.0, PI / 2.0, 0.0);
+        assert_near(entity.get_direction(), Vec3f::new(1.0, 0.0, 0.0));
+
+        // Pitch is applied before yaw, so the entity looks up and right
+        entity.set_rotation_euler(-PI / 4.0, PI / 2.0, 0.0);
+        assert_near(entity.get_direction(), Vec3f::new(1.0, 1.0, 0.0).normalize());
+    }
+
+    #[test]
+    fn rotations_accumulate() {
+        let mut twice = Entity::with_geometry(shapes::make_quad(1.0, 1.0, 0));
+        twice.rotate_pitch(0.3);
+        let mut once = twice.clone();
+        twice.rotate_yaw(0.5);
+        twice.rotate_yaw(0.5);
+        once.rotate_yaw(1.0);
+        assert_near(twice.get_direction(), once.get_direction());
+
+        // Rolling spins the entity around its forward axis
+        let forward = once.get_direction();
+        once.rotate_roll(1.2);
+        assert_near(once.get_direction(), forward);
+    }
+
+    #[test]
+    fn texcoords_outside_texture_sample_the_edge() {
+        let mut entity = Entity::with_geometry(shapes::make_quad(1.0, 1.0, 0));
+        let texture = Texture::new(vec![Color::RED, Color::GREEN, Color::BLUE, Color::WHITE], 2, 2);
+        entity.set_texture(TextureSlot::Diffuse, texture);
+        assert_eq!(entity.sample_texture((1.2, 0.0)), Color::GREEN);
+        assert_eq!(entity.sample_texture((-0.5, 3.0)), Color::BLUE);
+    }
+
+    #[test]
+    fn inverse_transform_point_reverses_transform_point() {
+        let mut entity = Entity::with_geometry(shapes::make_quad(1.0, 1.0, 0));
+        entity.set_translation(1.0, -2.0, 5.0);
+        entity.set_rotation_euler(0.3, -1.1, 0.7);
+        entity.set_scale_xyz(2.0, 0.5, 3.0);
+
+        let local = Vec3f::new(0.25, -4.0, 1.5);
+        let world = entity.transform_point(local);
+        assert!((world - local).length() > 1.0);
+        assert_near(entity.inverse_transform_point(world), local);
+    }
+
+    #[test]
+    fn rotates_around_offset_origin() {
+        // A door one unit wide, hinged on its left edge
+        let mut door = Entity::with_geometry(shapes::make_quad(1.0, 1.0, 0));
+        door.set_origin(Vec3f::new(-0.5, 0.0, 0.0));
+        assert_near(door.transform_point(Vec3f::new(0.5, 0.0, 0.0)), Vec3f::new(0.5, 0.0, 0.0));
+
+        door.rotate_yaw(PI / 2.0);
+        // The hinge stays put while the free edge swings around it
+        assert_near(door.transform_point(Vec3f::new(-0.5, 0.0, 0.0)), Vec3f::new(-0.5, 0.0, 0.0));
+        assert_near(door.transform_point(Vec3f::new(0.5, 0.0, 0.0)), Vec3f::new(-0.5, 0.0, -1.0));
+
+        door.set_translation(2.0, 0.0, 0.0);
+        door.set_scale(2.0);
+        let local = Vec3f::new(0.3, 0.2, -0.1);
+        assert_near(door.inverse_transform_point(door.transform_point(local)), local);
+    }
 }
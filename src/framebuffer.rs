@@ -0,0 +1,508 @@
+use std::{fmt::Write, io};
+
+use crate::{backend::{Backend, DefaultBackend}, canvas::{ColorDepth, DepthFunc}, texture::Color};
+
+/// How much of a pixel must be covered for it to be shown when
+/// antialiasing
+const COVERAGE_THRESHOLD: f32 = 0.25;
+
+/// Darkens pixels that the pixels around them are in front of, such as
+/// those inside creases and behind edges. This is an approximation
+/// done on the finished image, using only the depth buffer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AmbientOcclusion {
+    /// How many pixels away in every direction neighbors are compared
+    pub radius: usize,
+    /// How much nearer the average of two opposite neighbors has to be
+    /// to fully occlude a pixel. In depth buffer units on a
+    /// `FrameBuffer` and in world units on a `Canvas`.
+    pub depth_range: f32,
+    /// How much a pixel with every pair of neighbors fully occluding it is
+    /// darkened, from 0 (not at all) to 1 (black)
+    pub strength: f32,
+}
+
+/// The pixels and depths of an image, and how they're turned into
+/// braille characters. Knows nothing about cameras or projections, so
+/// it can be drawn to directly.
+pub struct FrameBuffer {
+    pub(crate) pixels: Vec<Option<Color>>,
+    pub(crate) depth: Vec<f32>,
+    coverage: Vec<f32>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    sample_factor: usize,
+    depth_func: DepthFunc,
+    depth_write: bool,
+    depth_clear_value: f32,
+    scissor: Option<(i32, i32, i32, i32)>,
+    texts: Vec<(u16, u16, String, Color)>,
+    clear_color: Option<Color>,
+    depth_view: bool,
+    gamma: f32,
+    color_depth: ColorDepth,
+    ambient_occlusion: Option<AmbientOcclusion>,
+    pixels_set: u64,
+    pixels_depth_rejected: u64,
+}
+
+impl FrameBuffer {
+    /// Creates an empty frame buffer of `width`x`height` pixels. Every
+    /// braille character is 2x4 pixels.
+    pub fn new(width: usize, height: usize) -> Self {
+        let depth_clear_value = f32::MIN;
+        Self {
+            pixels: vec![None; width * height],
+            depth: vec![depth_clear_value; width * height],
+            coverage: vec![0.0; width * height],
+            width,
+            height,
+            sample_factor: 1,
+            depth_func: DepthFunc::GreaterEqual,
+            depth_write: true,
+            depth_clear_value,
+            scissor: None,
+            texts: Vec::new(),
+            clear_color: None,
+            depth_view: false,
+            gamma: 1.0,
+            color_depth: ColorDepth::TrueColor,
+            ambient_occlusion: None,
+            pixels_set: 0,
+            pixels_depth_rejected: 0,
+        }
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, color: Color, depth: f32) {
+        let index = match self.writable_index(x, y, depth) {
+            Some(index) => index,
+            None => return,
+        };
+        self.pixels[index] = Some(color);
+        self.coverage[index] = 1.0;
+        self.pixels_set += 1;
+        if self.depth_write {
+            self.depth[index] = depth;
+        }
+    }
+
+    /// Blends `color` over the pixel, giving `color * alpha + existing *
+    /// (1 - alpha)`. Pixels that haven't been drawn to are treated as
+    /// black. Depth is only written for opaque (`alpha >= 1`) colors, so
+    /// that surfaces behind transparent ones can still be drawn.
+    pub fn set_blended(&mut self, x: i32, y: i32, color: Color, alpha: f32, depth: f32) {
+        let index = match self.writable_index(x, y, depth) {
+            Some(index) => index,
+            None => return,
+        };
+        let alpha = alpha.clamp(0.0, 1.0);
+        let existing = self.pixels[index].unwrap_or(Color::BLACK);
+        self.pixels[index] = Some(existing.lerp(color, alpha));
+        self.coverage[index] = 1.0;
+        self.pixels_set += 1;
+        if self.depth_write && alpha >= 1.0 {
+            self.depth[index] = depth;
+        }
+    }
+
+    /// Returns the index of pixel (`x`, `y`) if it's in the buffer,
+    /// inside the scissor rectangle and passes the depth test
+    fn writable_index(&mut self, x: i32, y: i32, depth: f32) -> Option<usize> {
+        if !(x >= 0 && x < self.width as i32 && y >= 0 && y < self.height as i32) {
+            return None;
+        }
+        if let Some((min_x, min_y, max_x, max_y)) = self.scissor {
+            if !(x >= min_x && x <= max_x && y >= min_y && y <= max_y) {
+                return None;
+            }
+        }
+        let index = (y * self.width as i32 + x) as usize;
+        if !self.depth_func.passes(depth, self.depth[index]) {
+            self.pixels_depth_rejected += 1;
+            return None;
+        }
+        Some(index)
+    }
+
+    /// Like `set`, but the pixel is only partly covered. Coverage from
+    /// triangles sharing an edge adds up.
+    pub(crate) fn set_with_coverage(&mut self, x: i32, y: i32, color: Color, depth: f32, coverage: f32) {
+        let index = (y * self.width as i32 + x) as usize;
+        let prior = if self.pixels[index].is_some() { self.coverage[index] } else { 0.0 };
+        self.set(x, y, color, depth);
+        if self.pixels[index].is_some() {
+            self.coverage[index] = (prior + coverage).min(1.0);
+        }
+    }
+
+    /// Restricts drawing to the rectangle (min x, min y, max x, max y),
+    /// both corners inclusive. `None` allows drawing anywhere.
+    pub fn set_scissor(&mut self, scissor: Option<(i32, i32, i32, i32)>) {
+        self.scissor = scissor;
+    }
+
+    /// Sets the test a pixel's depth has to pass to be drawn, see
+    /// `Canvas::set_depth_func`
+    pub fn set_depth_func(&mut self, depth_func: DepthFunc) {
+        self.depth_func = depth_func;
+    }
+
+    pub fn get_depth_func(&self) -> DepthFunc {
+        self.depth_func
+    }
+
+    /// Sets the depth `clear` fills the depth buffer with. Takes effect
+    /// on the next `clear`.
+    pub fn set_depth_clear_value(&mut self, depth: f32) {
+        self.depth_clear_value = depth;
+    }
+
+    pub fn get_depth_clear_value(&self) -> f32 {
+        self.depth_clear_value
+    }
+
+    /// Sets whether pixels that pass the depth test update the depth
+    /// buffer
+    pub fn set_depth_write(&mut self, depth_write: bool) {
+        self.depth_write = depth_write;
+    }
+
+    /// Sets the color of the terminal behind the image, or leaves the
+    /// terminal's own background if `None`
+    pub fn set_clear_color(&mut self, color: Option<Color>) {
+        self.clear_color = color;
+    }
+
+    pub fn clear(&mut self) {
+        let pixs = self.width * self.height;
+        self.pixels = vec![None; pixs];
+        self.coverage = vec![0.0; pixs];
+        self.depth = vec![self.depth_clear_value; pixs];
+        self.texts.clear();
+        self.pixels_set = 0;
+        self.pixels_depth_rejected = 0;
+    }
+
+    /// Returns how many pixels have been set and how many failed the
+    /// depth test since the last `clear`
+    pub fn pixel_counts(&self) -> (u64, u64) {
+        (self.pixels_set, self.pixels_depth_rejected)
+    }
+
+    /// Writes `text` on top of the image, starting at the character
+    /// cell (`col`, `row`). Both are 0-based. The text is kept until the
+    /// next `clear`.
+    pub fn draw_text(&mut self, col: u16, row: u16, text: &str, color: Color) {
+        self.texts.push((col, row, text.to_string(), color));
+    }
+
+    /// Returns the pixels row by row, top to bottom. Pixels that haven't
+    /// been drawn to are black. The buffer is `width * height` long, see
+    /// `size`.
+    pub fn render_to_rgb(&self) -> Vec<[u8; 3]> {
+        self.pixels.iter().map(|p| {
+            let c = p.unwrap_or(Color::BLACK);
+            [c.r, c.g, c.b]
+        }).collect()
+    }
+
+    /// Stores `factor`x`factor` samples for every braille dot, which are
+    /// averaged into the dot's color. Coordinates and `size` are in
+    /// samples. Clears the buffer.
+    pub fn set_sample_factor(&mut self, factor: usize) {
+        assert!(factor > 0, "sample factor must be at least 1");
+        self.width = self.width / self.sample_factor * factor;
+        self.height = self.height / self.sample_factor * factor;
+        self.sample_factor = factor;
+        self.clear();
+    }
+
+    pub fn get_sample_factor(&self) -> usize {
+        self.sample_factor
+    }
+
+    /// Resizes the buffer to `width`x`height` pixels, keeping its
+    /// supersampling. Clears the buffer.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width * self.sample_factor;
+        self.height = height * self.sample_factor;
+        self.clear();
+    }
+
+    /// Returns the size of the buffer in samples as (width, height).
+    /// Without supersampling every sample is a pixel.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Computes the resulting image as a string to be printed
+    pub fn to_s(&self) -> String {
+        self.to_s_with::<DefaultBackend>()
+    }
+
+    /// Writes the resulting image to `out`, which may be any writer such
+    /// as stdout, a file or a buffer, see `to_s`
+    pub fn write_to<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        self.write_to_with::<DefaultBackend, W>(out)
+    }
+
+    /// Writes the resulting image to `out` using the escape sequences
+    /// of backend `B`
+    pub fn write_to_with<B: Backend, W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(self.to_s_with::<B>().as_bytes())
+    }
+
+    /// Computes the resulting image as a string to be printed, using
+    /// the escape sequences of backend `B`
+    pub fn to_s_with<B: Backend>(&self) -> String {
+        let depth_range = if self.depth_view {
+            self.depth_range()
+        } else {
+            None
+        };
+        let occlusion = self.ambient_occlusion.map(|ao| self.occlusion(ao));
+        let mut string = String::with_capacity(self.pixels.len() * 3 / 2 + 4);
+        if let Some(color) = self.clear_color {
+            B::set_bg(&mut string, color);
+        }
+        B::clear(&mut string);
+        // Round up so that partial cells at the edges are drawn too
+        let rows = self.height.div_ceil(4 * self.sample_factor);
+        let cols = self.width.div_ceil(2 * self.sample_factor);
+        for row in 0..rows {
+            for col in 0..cols {
+                if let Some((braille, cel_color)) = self.cell(col, row, depth_range, occlusion.as_deref()) {
+                    B::goto(&mut string,
+                        (col as u16).saturating_add(1),
+                        (row as u16).saturating_add(1)
+                    );
+                    let cel_color = gamma_correct(cel_color, self.gamma);
+                    match self.color_depth {
+                        ColorDepth::TrueColor => B::set_fg(&mut string, cel_color),
+                        ColorDepth::Ansi256 => B::set_fg_indexed(&mut string, cel_color.to_ansi256()),
+                        ColorDepth::Ansi16 => B::set_fg_indexed(&mut string, cel_color.to_ansi16()),
+                    }
+                    string.write_char(braille).unwrap();
+                }
+            }
+        }
+        for (col, row, text, color) in &self.texts {
+            B::goto(&mut string, col.saturating_add(1), row.saturating_add(1));
+            B::set_fg(&mut string, *color);
+            string.write_str(text).unwrap();
+        }
+        string
+    }
+
+    /// Computes the braille character and color of the character cell
+    /// at (`col`, `row`). Returns `None` if none of its pixels are set.
+    ///
+    /// If `depth_range` is given, the color is instead the cell's average
+    /// depth normalized into the range, as a shade of gray.
+    ///
+    /// If `occlusion` is given, every pixel is darkened by its entry, see
+    /// `occlusion`.
+    pub(crate) fn cell(
+        &self,
+        col: usize,
+        row: usize,
+        depth_range: Option<(f32, f32)>,
+        occlusion: Option<&[f32]>,
+    ) -> Option<(char, Color)> {
+        const INDEX_OFFSETS: [(usize, usize); 8] = [
+            (0, 0), (0, 1), (0, 2),
+            (1, 0), (1, 1), (1, 2),
+            (0, 3), (1, 3),
+        ];
+        let k = self.sample_factor;
+        let (pix_row, pix_col) = (row * 4, col * 2);
+        let mut braille_code = 0x2800;
+        let mut color_sum = (0, 0, 0);
+        let mut depth_sum = 0.0;
+        let mut set_count = 0;
+        for (i, (dx, dy)) in INDEX_OFFSETS.iter().enumerate() {
+            // Every braille dot covers a k*k block of samples, and is
+            // shown if any of them are set
+            for sy in 0..k {
+                for sx in 0..k {
+                    let (x, y) = ((pix_col + dx) * k + sx, (pix_row + dy) * k + sy);
+                    // Cells at the edges may only be partly in the buffer
+                    if x >= self.width || y >= self.height {
+                        continue;
+                    }
+                    let index = y * self.width + x;
+                    if let Some(p_color) = self.pixels[index] {
+                        let coverage = self.coverage[index];
+                        if coverage >= COVERAGE_THRESHOLD {
+                            braille_code |= 1 << i;
+                        }
+                        let light = coverage * occlusion.map_or(1.0, |o| 1.0 - o[index]);
+                        color_sum.0 += (p_color.r as f32 * light) as u32;
+                        color_sum.1 += (p_color.g as f32 * light) as u32;
+                        color_sum.2 += (p_color.b as f32 * light) as u32;
+                        depth_sum += self.depth[index];
+                        set_count += 1;
+                    }
+                }
+            }
+        }
+        if braille_code == 0x2800 {
+            return None;
+        }
+        // Cells with fewer samples set are dimmer, as if the unset samples
+        // were black
+        let samples = (8 * k * k) as u32;
+        let mut cel_color = Color::new(
+            (color_sum.0 / samples) as u8,
+            (color_sum.1 / samples) as u8,
+            (color_sum.2 / samples) as u8,
+        );
+        if let Some((min, max)) = depth_range {
+            // Nearer pixels have larger depths and are drawn brighter
+            let depth = depth_sum / set_count as f32;
+            let shade = if max > min { (depth - min) / (max - min) } else { 1.0 };
+            cel_color = Color::WHITE * shade;
+        }
+        Some((char::from_u32(braille_code).unwrap(), cel_color))
+    }
+
+    /// Computes how much every pixel is darkened by `ao`, from 0 to
+    /// `ao.strength`. Every pair of opposite neighbors within the radius
+    /// occludes the pixel by how much nearer their average depth is than
+    /// the pixel's, relative to `ao.depth_range`, and the pixel is
+    /// darkened by the average. Flat surfaces, however steep, are left as
+    /// they are, and only creases are darkened.
+    fn occlusion(&self, ao: AmbientOcclusion) -> Vec<f32> {
+        // Bends smaller than this fraction of the depth range are only
+        // rounding errors on flat surfaces
+        const TOLERANCE: f32 = 1e-3;
+        let r = ao.radius as i32;
+        // One of every pair of opposite offsets
+        let offsets: Vec<(i32, i32)> = (-r..=r)
+            .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+            .filter(|&(dx, dy)| dy > 0 || (dy == 0 && dx > 0))
+            .collect();
+        let pairs = offsets.len().max(1) as f32;
+        let (w, h) = (self.width as i32, self.height as i32);
+        let depth_at = |x: i32, y: i32| {
+            let index = (y * w + x) as usize;
+            if x < 0 || y < 0 || x >= w || y >= h || self.pixels[index].is_none() {
+                return None;
+            }
+            Some(self.depth[index])
+        };
+        let mut occlusion = vec![0.0; self.pixels.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let depth = match depth_at(x, y) {
+                    Some(depth) => depth,
+                    None => continue,
+                };
+                let mut occluded = 0.0;
+                for &(dx, dy) in &offsets {
+                    if let (Some(a), Some(b)) = (depth_at(x + dx, y + dy), depth_at(x - dx, y - dy)) {
+                        // Nearer pixels have larger depths, so this is
+                        // positive where the surface bends towards the
+                        // camera around the pixel
+                        let bend = ((a + b) / 2.0 - depth) / ao.depth_range;
+                        if bend > TOLERANCE {
+                            occluded += bend.min(1.0);
+                        }
+                    }
+                }
+                occlusion[(y * w + x) as usize] = ao.strength.clamp(0.0, 1.0) * occluded / pairs;
+            }
+        }
+        occlusion
+    }
+
+    /// Sets the ambient occlusion `to_s` darkens pixels with, or `None`,
+    /// the default, to not darken them
+    pub fn set_ambient_occlusion(&mut self, ambient_occlusion: Option<AmbientOcclusion>) {
+        self.ambient_occlusion = ambient_occlusion;
+    }
+
+    /// Returns the smallest and largest depth of the set pixels
+    pub(crate) fn depth_range(&self) -> Option<(f32, f32)> {
+        self.pixels.iter().zip(&self.depth)
+            .filter(|(p, _)| p.is_some())
+            .map(|(_, d)| *d)
+            .fold(None, |range, d| match range {
+                None => Some((d, d)),
+                Some((min, max)) => Some((d.min(min), d.max(max))),
+            })
+    }
+
+    /// Sets whether `to_s` shows the depth buffer as shades of gray,
+    /// nearer being brighter, instead of the colors.
+    pub fn set_depth_view(&mut self, depth_view: bool) {
+        self.depth_view = depth_view;
+    }
+
+    /// Sets the gamma applied to every cell color by `to_s`. Values
+    /// above 1.0 brighten mid-tones, 1.0 leaves colors unchanged.
+    pub fn set_gamma(&mut self, gamma: f32) {
+        self.gamma = gamma;
+    }
+
+    /// Sets which colors `to_s` may emit. Cell colors are quantized to
+    /// the nearest palette entry when the terminal lacks truecolor.
+    pub fn set_color_depth(&mut self, color_depth: ColorDepth) {
+        self.color_depth = color_depth;
+    }
+}
+
+/// Applies `(c / 255)^(1 / gamma) * 255` to every channel of `color`
+fn gamma_correct(color: Color, gamma: f32) -> Color {
+    if gamma == 1.0 {
+        return color;
+    }
+    let correct = |c: u8| ((c as f32 / 255.0).powf(1.0 / gamma) * 255.0).round() as u8;
+    Color::new(correct(color.r), correct(color.g), correct(color.b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gamma_brightens_mid_gray() {
+        let corrected = gamma_correct(Color::GRAY, 2.2);
+        assert_eq!(corrected.r, 186);
+        assert_eq!((corrected.g, corrected.b), (186, 186));
+        assert_eq!(gamma_correct(Color::GRAY, 1.0).r, 128);
+    }
+
+    #[test]
+    fn standalone_buffer_draws_without_camera() {
+        let mut frame = FrameBuffer::new(4, 8);
+        frame.set(0, 0, Color::WHITE, 0.0);
+        frame.set(1, 4, Color::WHITE, 0.0);
+        assert_eq!(frame.pixel_counts(), (2, 0));
+        let s = frame.to_s();
+        // The top left dot of cell (0, 0) and the top right one of (0, 1)
+        assert!(s.contains('\u{2801}'));
+        assert!(s.contains('\u{2808}'));
+        frame.clear();
+        assert!(!frame.to_s().contains('\u{2801}'));
+    }
+
+    #[test]
+    fn crease_is_darker_than_flat_slopes() {
+        // Two slopes meeting in a crease at x = 16, which is furthest away
+        let mut frame = FrameBuffer::new(32, 8);
+        for y in 0..8 {
+            for x in 0..32 {
+                frame.set(x, y, Color::WHITE, (x - 16).abs() as f32 * 0.01);
+            }
+        }
+        let (_, unlit) = frame.cell(8, 0, None, None).unwrap();
+        frame.set_ambient_occlusion(Some(AmbientOcclusion { radius: 2, depth_range: 0.05, strength: 1.0 }));
+        let occlusion = frame.occlusion(frame.ambient_occlusion.unwrap());
+        let cell = |col| frame.cell(col, 0, None, Some(&occlusion)).unwrap().1;
+        let (crease, slope) = (cell(8), cell(3));
+        assert!(crease.r < slope.r);
+        assert_eq!(slope, unlit);
+        assert!(frame.to_s().contains(&crease.to_ansi_fg()));
+    }
+}
@@ -0,0 +1,238 @@
+#[cfg(feature = "device_query")]
+use device_query::{DeviceQuery, DeviceState, Keycode};
+
+#[cfg(not(feature = "device_query"))]
+use crate::backend::{Backend, DefaultBackend};
+
+/// A key on the keyboard, independent of where the input was read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Esc,
+    /// A letter pressed while holding Ctrl, in lowercase
+    Ctrl(char),
+}
+
+/// Reads the keyboard once per frame.
+///
+/// By default keys are read from the terminal itself, so input only
+/// arrives while the terminal is focused and works over SSH. With the
+/// `device_query` feature the global keyboard state is polled instead,
+/// which needs OS-level access but reports keys for as long as they
+/// are held down.
+pub struct Input {
+    #[cfg(feature = "device_query")]
+    device_state: DeviceState,
+    #[cfg(not(feature = "device_query"))]
+    terminal: <DefaultBackend as Backend>::Input,
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "device_query")]
+            device_state: DeviceState::new(),
+            #[cfg(not(feature = "device_query"))]
+            terminal: DefaultBackend::input(),
+        }
+    }
+
+    /// Returns the keys active this frame, without blocking
+    pub fn poll(&mut self) -> Vec<Key> {
+        #[cfg(feature = "device_query")]
+        {
+            from_keycodes(&self.device_state.get_keys())
+        }
+        #[cfg(not(feature = "device_query"))]
+        {
+            DefaultBackend::poll_keys(&mut self.terminal)
+        }
+    }
+
+    /// Returns the mouse position in pixels, or `None` if it can't be
+    /// read. Only the `device_query` feature can read the mouse.
+    pub fn mouse_position(&self) -> Option<(i32, i32)> {
+        #[cfg(feature = "device_query")]
+        {
+            Some(self.device_state.get_mouse().coords)
+        }
+        #[cfg(not(feature = "device_query"))]
+        {
+            None
+        }
+    }
+
+    /// Polls the keys and mouse and returns the state of the frame after
+    /// `prev`
+    pub fn poll_state(&mut self, prev: &InputState) -> InputState {
+        let keys = self.poll();
+        prev.next(keys, self.mouse_position())
+    }
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The keys held down and the mouse position during one frame, along
+/// with those of the frame before, so that presses can be told apart
+/// from keys being held. Doesn't depend on which input backend is used.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputState {
+    keys: Vec<Key>,
+    prev_keys: Vec<Key>,
+    mouse: Option<(i32, i32)>,
+    prev_mouse: Option<(i32, i32)>,
+}
+
+impl InputState {
+    /// Creates the state of a first frame, where nothing was held before
+    pub fn new(keys: Vec<Key>, mouse: Option<(i32, i32)>) -> Self {
+        Self { keys, mouse, ..Self::default() }
+    }
+
+    /// Returns the state of the next frame, with this one as the
+    /// previous frame
+    pub fn next(&self, keys: Vec<Key>, mouse: Option<(i32, i32)>) -> Self {
+        Self {
+            keys,
+            prev_keys: self.keys.clone(),
+            mouse,
+            prev_mouse: self.mouse,
+        }
+    }
+
+    pub fn is_key_down(&self, key: Key) -> bool {
+        self.keys.contains(&key)
+    }
+
+    /// Returns true if `key` is down this frame but wasn't the frame
+    /// before
+    pub fn just_pressed(&self, key: Key) -> bool {
+        self.is_key_down(key) && !self.prev_keys.contains(&key)
+    }
+
+    /// Returns every key held down, in the order they were read
+    pub fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    /// Returns the mouse position in pixels, or `None` if the input
+    /// backend can't read the mouse
+    pub fn mouse_position(&self) -> Option<(i32, i32)> {
+        self.mouse
+    }
+
+    /// Returns how far the mouse moved since the previous frame, or
+    /// (0, 0) if either position is unknown
+    pub fn mouse_delta(&self) -> (i32, i32) {
+        match (self.mouse, self.prev_mouse) {
+            (Some((x, y)), Some((px, py))) => (x - px, y - py),
+            _ => (0, 0),
+        }
+    }
+}
+
+/// Converts the keys held down, turning letters into `Key::Ctrl` while
+/// either Ctrl key is held, as terminals report them
+#[cfg(feature = "device_query")]
+fn from_keycodes(keycodes: &[Keycode]) -> Vec<Key> {
+    let ctrl = keycodes.iter().any(|k| matches!(k, Keycode::LControl | Keycode::RControl));
+    keycodes.iter().filter_map(from_keycode).map(|key| match key {
+        Key::Char(c) if ctrl && c.is_ascii_alphabetic() => Key::Ctrl(c),
+        key => key,
+    }).collect()
+}
+
+#[cfg(feature = "device_query")]
+fn from_keycode(keycode: &Keycode) -> Option<Key> {
+    use device_query::Keycode::*;
+    let key = match keycode {
+        Escape => Key::Esc,
+        Up => Key::Up,
+        Down => Key::Down,
+        Left => Key::Left,
+        Right => Key::Right,
+        Space => Key::Char(' '),
+        A => Key::Char('a'), B => Key::Char('b'), C => Key::Char('c'), D => Key::Char('d'), E => Key::Char('e'), F => Key::Char('f'),
+        G => Key::Char('g'), H => Key::Char('h'), I => Key::Char('i'), J => Key::Char('j'), K => Key::Char('k'), L => Key::Char('l'),
+        M => Key::Char('m'), N => Key::Char('n'), O => Key::Char('o'), P => Key::Char('p'), Q => Key::Char('q'), R => Key::Char('r'),
+        S => Key::Char('s'), T => Key::Char('t'), U => Key::Char('u'), V => Key::Char('v'), W => Key::Char('w'), X => Key::Char('x'),
+        Y => Key::Char('y'), Z => Key::Char('z'),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Decodes all keys currently available from a termion key stream.
+/// Stops at the first read that has nothing to offer, so this doesn't
+/// block when reading from `termion::async_stdin`.
+#[cfg(feature = "termion")]
+pub fn read_termion_keys<R: std::io::Read>(keys: &mut termion::input::Keys<R>) -> Vec<Key> {
+    use termion::event::Key as TKey;
+    let mut pressed = Vec::new();
+    while let Some(Ok(key)) = keys.next() {
+        let key = match key {
+            TKey::Char(c) => Key::Char(c.to_ascii_lowercase()),
+            TKey::Ctrl(c) => Key::Ctrl(c.to_ascii_lowercase()),
+            TKey::Up => Key::Up,
+            TKey::Down => Key::Down,
+            TKey::Left => Key::Left,
+            TKey::Right => Key::Right,
+            TKey::Esc => Key::Esc,
+            _ => continue,
+        };
+        pressed.push(key);
+    }
+    pressed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_state_tracks_presses_and_mouse() {
+        let first = InputState::new(vec![Key::Char('w')], Some((10, 20)));
+        assert!(first.is_key_down(Key::Char('w')));
+        assert!(first.just_pressed(Key::Char('w')));
+        assert_eq!(first.mouse_delta(), (0, 0));
+
+        let second = first.next(vec![Key::Char('w'), Key::Up], Some((13, 18)));
+        assert!(second.is_key_down(Key::Char('w')));
+        assert!(!second.just_pressed(Key::Char('w')));
+        assert!(second.just_pressed(Key::Up));
+        assert!(!second.is_key_down(Key::Esc));
+        assert_eq!(second.mouse_position(), Some((13, 18)));
+        assert_eq!(second.mouse_delta(), (3, -2));
+
+        let third = second.next(Vec::new(), None);
+        assert!(!third.is_key_down(Key::Char('w')));
+        assert_eq!(third.mouse_delta(), (0, 0));
+    }
+
+    #[cfg(feature = "termion")]
+    #[test]
+    fn decodes_terminal_byte_stream() {
+        use termion::input::TermRead;
+
+        let bytes: &[u8] = b"w\x1b[AQ\x03";
+        let keys = read_termion_keys(&mut bytes.keys());
+        assert_eq!(keys, vec![Key::Char('w'), Key::Up, Key::Char('q'), Key::Ctrl('c')]);
+    }
+
+    #[cfg(feature = "device_query")]
+    #[test]
+    fn held_ctrl_turns_letters_into_ctrl_keys() {
+        let keys = from_keycodes(&[Keycode::LControl, Keycode::C, Keycode::Up]);
+        assert_eq!(keys, vec![Key::Ctrl('c'), Key::Up]);
+        assert_eq!(from_keycodes(&[Keycode::C]), vec![Key::Char('c')]);
+        assert_eq!(from_keycodes(&[Keycode::RControl, Keycode::Space]), vec![Key::Char(' ')]);
+    }
+}
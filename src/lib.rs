@@ -1,8 +1,13 @@
+pub mod backend;
 pub mod canvas;
 pub mod entity;
+pub mod framebuffer;
+pub mod input;
 pub mod shapes;
 pub mod vertex;
+pub mod material;
 pub mod math;
+pub mod scene;
 pub mod window;
 pub mod texture;
 
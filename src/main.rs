@@ -1,12 +1,69 @@
+pub mod backend;
 pub mod canvas;
 pub mod entity;
+pub mod framebuffer;
+pub mod input;
 pub mod shapes;
 pub mod vertex;
+pub mod material;
 pub mod math;
+pub mod scene;
 pub mod window;
 pub mod texture;
 
+use std::process;
+
+use crate::window::{Window, WindowConfig};
+
+const USAGE: &str = "\
+Usage: braillegl [OPTIONS] [MODEL.obj]
+
+Renders MODEL.obj (default: res/objects/teapot.obj) in the terminal.
+
+Options:
+    --scale <SCALE>        initial scale of the model (default: 1.0)
+    --distance <DISTANCE>  distance from the camera to the model (default: 6.0)
+    --fov <DEGREES>        field of view of the projection (default: 90.0)
+    --cell-aspect <RATIO>  width of a terminal cell over its height (default: 0.5)
+    -h, --help             print this message and exit";
+
 fn main() {
-    let window = crate::window::Window::default();
+    let window = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(window)) => window,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(msg) => {
+            eprintln!("error: {}\n\n{}", msg, USAGE);
+            process::exit(1);
+        },
+    };
     window.run();
-}
\ No newline at end of file
+}
+
+/// Parses the command-line arguments into a window. Returns `Ok(None)`
+/// if the user asked for help.
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Window>, String> {
+    let mut config = WindowConfig::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--scale" => config.scale = parse_value(&arg, args.next())?,
+            "--distance" => config.distance = parse_value(&arg, args.next())?,
+            "--fov" => config.fov = parse_value(&arg, args.next())?,
+            "--cell-aspect" => config.cell_aspect = parse_value(&arg, args.next())?,
+            flag if flag.starts_with("--") => {
+                return Err(format!("unknown option '{}'", flag));
+            },
+            _ => config.model_path = arg,
+        }
+    }
+    Ok(Some(Window::new(config)))
+}
+
+fn parse_value(flag: &str, value: Option<String>) -> Result<f32, String> {
+    let value = value.ok_or_else(|| format!("'{}' expects a value", flag))?;
+    value.parse().map_err(|_| format!("invalid value '{}' for '{}'", value, flag))
+}
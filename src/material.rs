@@ -0,0 +1,65 @@
+use std::{fs::File, io::{BufRead, BufReader}, path::Path};
+
+use crate::texture::{Color, Texture, WrapMode};
+
+/// The look of a surface, as described by a `.mtl` file
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Material {
+    pub name: String,
+    /// The diffuse color (`Kd`), used where there is no texture
+    pub diffuse: Color,
+    /// The diffuse texture (`map_Kd`). It has `flip_v` set, since OBJ
+    /// texcoords put `v = 0` at the bottom.
+    pub texture: Option<Texture>,
+}
+
+impl Material {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            diffuse: Color::WHITE,
+            texture: None,
+        }
+    }
+
+    /// Returns the color of the material at the texcoords `uv`, or the
+    /// diffuse color if there is no texture or texcoords
+    pub fn sample(&self, uv: Option<(f32, f32)>) -> Color {
+        match (&self.texture, uv) {
+            (Some(texture), Some((u, v))) => texture.sample(u, v),
+            _ => self.diffuse,
+        }
+    }
+}
+
+/// Loads every material in a `.mtl` file. Only the diffuse color and
+/// texture are read, textures are looked up relative to the file and
+/// repeat, as texcoords of tiled materials go beyond [0, 1]. Their rows
+/// are flipped to match the bottom left origin of OBJ texcoords.
+pub fn load_mtl<P: AsRef<Path>>(filepath: P) -> Vec<Material> {
+    let filepath = filepath.as_ref();
+    let dir = filepath.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials: Vec<Material> = Vec::new();
+
+    let reader = BufReader::new(File::open(filepath).unwrap());
+    for line in reader.lines() {
+        let line = line.unwrap();
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix("newmtl ") {
+            materials.push(Material::new(name.trim()));
+        } else if let (Some(rest), Some(material)) = (line.strip_prefix("Kd "), materials.last_mut()) {
+            let c = rest.split_whitespace().map(|x|
+                x.parse().unwrap()
+            ).collect::<Vec<f32>>();
+            let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+            material.diffuse = Color::new(channel(c[0]), channel(c[1]), channel(c[2]));
+        } else if let (Some(path), Some(material)) = (line.strip_prefix("map_Kd "), materials.last_mut()) {
+            let mut texture = Texture::load_from_file(dir.join(path.trim()));
+            texture.set_wrap_mode(WrapMode::Repeat);
+            texture.set_flip_v(true);
+            material.texture = Some(texture);
+        }
+    }
+    materials
+}
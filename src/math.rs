@@ -1,6 +1,7 @@
 use std::{f32::consts::PI, ops::{Add, AddAssign, Mul, Neg, Sub}};
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vec3f {
     pub x: f32,
     pub y: f32,
@@ -40,6 +41,19 @@ impl Vec3f {
     pub fn dot(&self, rhs: &Self) -> f32 {
         self.x*rhs.x + self.y*rhs.y + self.z*rhs.z
     }
+
+    /// Reflects the vector off a surface with the (normalized) `normal`
+    pub fn reflect(&self, normal: &Self) -> Self {
+        *self - normal.scale(2.0 * self.dot(normal))
+    }
+
+    /// Rotates the vector `angle` radians around `axis`, counter-clockwise
+    /// when looking down the axis towards the origin (Rodrigues' formula)
+    pub fn rotate_around_axis(&self, axis: Vec3f, angle: f32) -> Self {
+        let k = axis.normalize();
+        let (sin, cos) = angle.sin_cos();
+        self.scale(cos) + k.cross(self).scale(sin) + k.scale(k.dot(self) * (1.0 - cos))
+    }
 }
 
 impl Add for Vec3f {
@@ -71,7 +85,123 @@ impl Sub for Vec3f {
     }
 }
 
-#[derive(Clone, Copy)]
+/// A quaternion representing a rotation. Only unit quaternions are
+/// valid rotations, the constructors below always return those.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quat {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quat {
+    pub const fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
+        Self { w, x, y, z }
+    }
+
+    pub const fn identity() -> Self {
+        Self::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    /// Creates a rotation of `angle` radians around `axis`, counter-clockwise
+    /// when looking down the axis towards the origin
+    pub fn from_axis_angle(axis: Vec3f, angle: f32) -> Self {
+        let axis = axis.normalize();
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Self::new(cos, axis.x * sin, axis.y * sin, axis.z * sin)
+    }
+
+    /// Creates the rotation turning +Z towards `direction` without any
+    /// roll, by first pitching around X and then yawing around Y
+    pub fn look_rotation(direction: Vec3f) -> Self {
+        let d = direction.normalize();
+        let yaw = d.x.atan2(d.z);
+        let pitch = -d.y.clamp(-1.0, 1.0).asin();
+        Self::from_axis_angle(Vec3f::new(0.0, 1.0, 0.0), yaw)
+            * Self::from_axis_angle(Vec3f::new(1.0, 0.0, 0.0), pitch)
+    }
+
+    /// Returns the inverse rotation
+    pub fn conjugate(&self) -> Self {
+        Self::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn normalize(&self) -> Self {
+        let l = (self.w*self.w + self.x*self.x + self.y*self.y + self.z*self.z).sqrt();
+        Self::new(self.w / l, self.x / l, self.y / l, self.z / l)
+    }
+
+    /// Rotates `v` by this quaternion
+    pub fn rotate(&self, v: &Vec3f) -> Vec3f {
+        self.to_matrix().vecmul(v, false)
+    }
+
+    pub fn to_matrix(&self) -> Mat4x4f {
+        let Self { w, x, y, z } = *self;
+        Mat4x4f::new(
+            1.0 - 2.0*(y*y + z*z), 2.0*(x*y - w*z), 2.0*(x*z + w*y), 0.0,
+            2.0*(x*y + w*z), 1.0 - 2.0*(x*x + z*z), 2.0*(y*z - w*x), 0.0,
+            2.0*(x*z - w*y), 2.0*(y*z + w*x), 1.0 - 2.0*(x*x + y*y), 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        )
+    }
+}
+
+impl Mul<Quat> for Quat {
+    type Output = Quat;
+    /// Combines two rotations, `rhs` is applied first
+    fn mul(self, rhs: Quat) -> Self::Output {
+        Quat::new(
+            self.w*rhs.w - self.x*rhs.x - self.y*rhs.y - self.z*rhs.z,
+            self.w*rhs.x + self.x*rhs.w + self.y*rhs.z - self.z*rhs.y,
+            self.w*rhs.y - self.x*rhs.z + self.y*rhs.w + self.z*rhs.x,
+            self.w*rhs.z + self.x*rhs.y - self.y*rhs.x + self.z*rhs.w,
+        )
+    }
+}
+
+/// A plane of all points `p` where `normal.dot(p) + d == 0`
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub normal: Vec3f,
+    pub d: f32,
+}
+
+impl Plane {
+    /// Returns the signed distance from the plane to `point`, which is
+    /// positive on the side the normal points towards
+    pub fn distance(&self, point: &Vec3f) -> f32 {
+        self.normal.dot(point) + self.d
+    }
+}
+
+/// The six planes bounding what a camera can see, created with
+/// `Mat4x4f::frustum`
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Returns false if the axis-aligned box from `min` to `max` lies
+    /// entirely outside the frustum. Boxes near the corners of the
+    /// frustum may be reported as intersecting even if they don't.
+    pub fn intersects_box(&self, min: Vec3f, max: Vec3f) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal
+            let p = Vec3f::new(
+                if plane.normal.x >= 0.0 { max.x } else { min.x },
+                if plane.normal.y >= 0.0 { max.y } else { min.y },
+                if plane.normal.z >= 0.0 { max.z } else { min.z },
+            );
+            plane.distance(&p) >= 0.0
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Mat4x4f {
     pub m: [[f32; 4]; 4],
 }
@@ -101,6 +231,14 @@ impl Mat4x4f {
         )
     }
 
+    /// Returns true if every element differs from the one in `other`
+    /// by at most `eps`
+    pub fn approx_eq(&self, other: &Mat4x4f, eps: f32) -> bool {
+        self.m.iter().flatten()
+            .zip(other.m.iter().flatten())
+            .all(|(a, b)| (a - b).abs() <= eps)
+    }
+
     pub fn vecmul(&self, rhs: &Vec3f, translate: bool) -> Vec3f {
         let t = if translate { 1.0 } else { 0.0 };
         let m = self.m;
@@ -118,14 +256,82 @@ impl Mat4x4f {
         }
     }
 
+    /// Same as `projection_fov` with the arguments in another order
     pub fn projection(aspect_ratio: f32, fov: f32, znear: f32, zfar: f32) -> Self {
-        let angle = fov * PI / 180.0;
+        Self::projection_fov(fov, aspect_ratio, znear, zfar)
+    }
+
+    /// Creates a perspective projection for a camera looking down -Z,
+    /// seeing `fov_y` degrees vertically. The horizontal field of view
+    /// follows from `aspect_ratio` (width / height).
+    /// 
+    /// After dividing by w, points inside the view have x and y in
+    /// [-1, 1] and z going from 1 at `znear` to -1 at `zfar`, so nearer
+    /// points get larger depths.
+    pub fn projection_fov(fov_y: f32, aspect_ratio: f32, znear: f32, zfar: f32) -> Self {
+        let angle = fov_y * PI / 180.0;
         let f = 1.0 / (angle / 2.0).tan();
         Self::new(
             f / aspect_ratio, 0.0, 0.0, 0.0,
             0.0, f, 0.0, 0.0,
-            0.0, 0.0, (znear + zfar) / (zfar - znear), -1.0,
-            0.0, 0.0, 2.0*znear*zfar / (zfar - znear), 0.0,
+            0.0, 0.0, (znear + zfar) / (zfar - znear), 2.0*znear*zfar / (zfar - znear),
+            0.0, 0.0, -1.0, 0.0,
+        )
+    }
+
+    /// Extracts the planes of the view frustum from a projection (or
+    /// combined view-projection) matrix. The planes are in the space the
+    /// matrix transforms from, with their normals pointing inwards.
+    pub fn frustum(&self) -> Frustum {
+        let m = self.m;
+        let plane = |sign: f32, row: usize| {
+            let normal = Vec3f::new(
+                m[3][0] + sign * m[row][0],
+                m[3][1] + sign * m[row][1],
+                m[3][2] + sign * m[row][2],
+            );
+            let l = normal.length();
+            Plane { normal: normal.scale(1.0 / l), d: (m[3][3] + sign * m[row][3]) / l }
+        };
+        Frustum {
+            planes: [
+                plane(1.0, 0), plane(-1.0, 0),
+                plane(1.0, 1), plane(-1.0, 1),
+                plane(1.0, 2), plane(-1.0, 2),
+            ],
+        }
+    }
+
+    /// Creates a view matrix for a camera at `eye` looking at `target`.
+    /// In view space the camera sits at the origin looking down -Z,
+    /// with +Y up.
+    pub fn look_at(eye: Vec3f, target: Vec3f, up: Vec3f) -> Self {
+        let z_axis = (eye - target).normalize();
+        let x_axis = up.cross(&z_axis).normalize();
+        let y_axis = z_axis.cross(&x_axis);
+        Self::new(
+            x_axis.x, x_axis.y, x_axis.z, -x_axis.dot(&eye),
+            y_axis.x, y_axis.y, y_axis.z, -y_axis.dot(&eye),
+            z_axis.x, z_axis.y, z_axis.z, -z_axis.dot(&eye),
+            0.0, 0.0, 0.0, 1.0,
+        )
+    }
+
+    pub fn translation(x: f32, y: f32, z: f32) -> Self {
+        Self::new(
+            1.0, 0.0, 0.0, x,
+            0.0, 1.0, 0.0, y,
+            0.0, 0.0, 1.0, z,
+            0.0, 0.0, 0.0, 1.0,
+        )
+    }
+
+    pub fn scaling(sx: f32, sy: f32, sz: f32) -> Self {
+        Self::new(
+            sx, 0.0, 0.0, 0.0,
+            0.0, sy, 0.0, 0.0,
+            0.0, 0.0, sz, 0.0,
+            0.0, 0.0, 0.0, 1.0,
         )
     }
 
@@ -194,4 +400,50 @@ impl Mul<Mat4x4f> for Mat4x4f {
         }
         result
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edge_of_vertical_fov_maps_to_edge_of_clip_space() {
+        let projection = Mat4x4f::projection_fov(90.0, 2.0, 0.1, 100.0);
+        let top = projection.vecmul(&Vec3f::new(0.0, 1.0, -1.0), true);
+        let bottom = projection.vecmul(&Vec3f::new(0.0, -5.0, -5.0), true);
+        assert!((top.y - 1.0).abs() < 1e-5);
+        assert!((bottom.y + 1.0).abs() < 1e-5);
+
+        // The aspect ratio widens the view instead of squashing it
+        let right = projection.vecmul(&Vec3f::new(2.0, 0.0, -1.0), true);
+        assert!((right.x - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn quarter_turn_around_z_takes_x_to_y() {
+        let v = Vec3f::new(1.0, 0.0, 0.0).rotate_around_axis(Vec3f::new(0.0, 0.0, 2.0), PI / 2.0);
+        assert!((v - Vec3f::new(0.0, 1.0, 0.0)).length() < 1e-5);
+
+        // Agrees with the quaternion rotation
+        let axis = Vec3f::new(1.0, 2.0, 3.0);
+        let p = Vec3f::new(-1.0, 0.5, 2.0);
+        let q = Quat::from_axis_angle(axis, 0.7).rotate(&p);
+        assert!((p.rotate_around_axis(axis, 0.7) - q).length() < 1e-5);
+    }
+
+    #[test]
+    fn multiplying_by_identity_keeps_matrix() {
+        let m = Mat4x4f::translation(1.0, -2.0, 3.0)
+            * Mat4x4f::rotate_y(0.4)
+            * Mat4x4f::scaling(2.0, 1.0, 0.5);
+        assert!((m * Mat4x4f::identity()).approx_eq(&m, 1e-6));
+        assert!((Mat4x4f::identity() * m).approx_eq(&m, 1e-6));
+        assert_eq!(m, m);
+
+        let mut off = m;
+        off.m[3][0] += 0.01;
+        assert_ne!(off, m);
+        assert!(!off.approx_eq(&m, 1e-3));
+        assert!(off.approx_eq(&m, 0.1));
+    }
+}
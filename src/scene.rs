@@ -0,0 +1,191 @@
+use std::{path::Path, slice::Iter};
+
+use crate::{entity::Entity, math::Mat4x4f, shapes};
+
+/// A collection of entities, where every entity may be attached to a
+/// parent entity. An attached entity's transform is relative to its
+/// parent, so moving the parent moves the child along with it.
+pub struct Scene {
+    entities: Vec<Entity>,
+    parents: Vec<Option<usize>>,
+    names: Vec<String>,
+}
+
+/// Returned by `Scene::set_parent` when the new parent is a descendant
+/// of the child, which would make the hierarchy loop
+#[derive(Debug, PartialEq)]
+pub struct ParentCycle;
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self { entities: Vec::new(), parents: Vec::new(), names: Vec::new() }
+    }
+
+    /// Loads an OBJ file with one entity for every object or group in
+    /// it, so that they can be moved independently, see
+    /// `shapes::load_groups_from_file`. Every entity is named after its
+    /// group.
+    pub fn load_from_obj<P: AsRef<Path>>(filepath: P) -> Self {
+        let mut scene = Self::new();
+        for (name, shape) in shapes::load_groups_from_file(filepath) {
+            scene.add_named(name, Entity::with_geometry(shape));
+        }
+        scene
+    }
+
+    /// Adds an entity without a parent and returns its index in the scene
+    pub fn add(&mut self, entity: Entity) -> usize {
+        self.add_named(String::new(), entity)
+    }
+
+    /// Like `add`, but names the entity so that it can be looked up with
+    /// `find`
+    pub fn add_named<S: Into<String>>(&mut self, name: S, entity: Entity) -> usize {
+        self.entities.push(entity);
+        self.parents.push(None);
+        self.names.push(name.into());
+        self.entities.len() - 1
+    }
+
+    /// Returns the name of the entity at `index`, empty if it has none
+    pub fn get_name(&self, index: usize) -> &str {
+        &self.names[index]
+    }
+
+    /// Returns the index of the first entity named `name`
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    pub fn get(&self, index: usize) -> &Entity {
+        &self.entities[index]
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut Entity {
+        &mut self.entities[index]
+    }
+
+    /// Returns the number of entities in the scene
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Returns an iterator over the entities, in the order they were added
+    pub fn entities(&self) -> Iter<'_, Entity> {
+        self.entities.iter()
+    }
+
+    /// Attaches the entity at `child` to the entity at `parent`, or
+    /// detaches it if `parent` is `None`. Fails without changing
+    /// anything if `parent` is `child` or one of its descendants.
+    pub fn set_parent(&mut self, child: usize, parent: Option<usize>) -> Result<(), ParentCycle> {
+        let mut ancestor = parent;
+        while let Some(index) = ancestor {
+            if index == child {
+                return Err(ParentCycle);
+            }
+            ancestor = self.parents[index];
+        }
+        self.parents[child] = parent;
+        Ok(())
+    }
+
+    pub fn get_parent(&self, index: usize) -> Option<usize> {
+        self.parents[index]
+    }
+
+    /// Generates the transform from the entity's local space into world
+    /// space, i.e. its local transform preceded by those of its ancestors
+    pub fn world_transform(&self, index: usize) -> Mat4x4f {
+        let mut transform = self.entities[index].gen_local_transform();
+        let mut ancestor = self.parents[index];
+        while let Some(parent) = ancestor {
+            transform = self.entities[parent].gen_local_transform() * transform;
+            ancestor = self.parents[parent];
+        }
+        transform
+    }
+
+    /// Generates the matrix transforming the entity's normals into world
+    /// space, see `Entity::gen_normal_transform`
+    pub fn world_normal_transform(&self, index: usize) -> Mat4x4f {
+        let mut transform = self.entities[index].gen_normal_transform();
+        let mut ancestor = self.parents[index];
+        while let Some(parent) = ancestor {
+            transform = self.entities[parent].gen_normal_transform() * transform;
+            ancestor = self.parents[parent];
+        }
+        transform
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec3f;
+
+    #[test]
+    fn child_follows_parent() {
+        let mut scene = Scene::new();
+        let planet = scene.add(Entity::with_geometry(shapes::make_icosphere(2.0, 0)));
+        let moon = scene.add(Entity::with_geometry(shapes::make_icosphere(0.5, 0)));
+        scene.get_mut(moon).set_translation(5.0, 0.0, 0.0);
+        scene.set_parent(moon, Some(planet)).unwrap();
+
+        scene.get_mut(planet).set_translation(0.0, 0.0, -10.0);
+        let moon_position = scene.world_transform(moon).vecmul(&Vec3f::zero(), true);
+        assert!((moon_position - Vec3f::new(5.0, 0.0, -10.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn parent_cycles_are_rejected() {
+        let mut scene = Scene::new();
+        let a = scene.add(Entity::with_geometry(shapes::make_icosphere(1.0, 0)));
+        let b = scene.add(Entity::with_geometry(shapes::make_icosphere(1.0, 0)));
+        scene.set_parent(b, Some(a)).unwrap();
+        assert_eq!(scene.set_parent(a, Some(b)), Err(ParentCycle));
+        assert_eq!(scene.set_parent(a, Some(a)), Err(ParentCycle));
+        assert_eq!(scene.get_parent(a), None);
+    }
+
+    #[test]
+    fn obj_objects_become_entities() {
+        let path = std::env::temp_dir().join(format!("braillegl-groups-{}.obj", std::process::id()));
+        std::fs::write(&path, "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 5 0 0
+v 6 0 0
+v 6 1 0
+o first
+f 1 2 3
+f 1 3 4
+o second
+f 5 6 7
+").unwrap();
+        let scene = Scene::load_from_obj(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(scene.len(), 2);
+        assert_eq!(scene.get(0).shape.triangle_count(), 2);
+        assert_eq!(scene.get(0).shape.vertex_count(), 4);
+        assert_eq!(scene.get(1).shape.triangle_count(), 1);
+        assert_eq!(scene.get(1).shape.vertex_count(), 3);
+        assert_eq!(scene.get(1).shape.get(0).position.x, 5.0);
+        assert_eq!(scene.get_name(0), "first");
+        assert_eq!(scene.find("second"), Some(1));
+        assert_eq!(scene.find("third"), None);
+    }
+}
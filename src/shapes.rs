@@ -1,10 +1,43 @@
-use std::{collections::HashMap, f32::consts::PI, fs::File, io::{BufRead, BufReader}, path::Path, slice::Iter};
+use std::{collections::HashMap, f32::consts::PI, fs::File, io::{BufRead, BufReader}, ops::Range, path::Path, rc::Rc, slice::Iter};
 
-use crate::{math::Vec3f, vertex::{Vertex, VertexArray}};
+use crate::{material::{self, Material}, math::Vec3f, texture::Color, vertex::{Vertex, VertexArray}};
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Shape {
-    va: VertexArray,
+    /// The vertex pool, shared by the shapes loaded from the groups of
+    /// one OBJ file. A shape copies its part out before changing it.
+    va: Rc<VertexArray>,
+    /// The vertices of `va` belonging to this shape. Triangle indices
+    /// are relative to the start of the range.
+    vertex_range: Range<usize>,
     triangles: Vec<(usize, usize, usize)>,
+    materials: Vec<Material>,
+    /// The index into `materials` for every triangle, empty if the shape
+    /// has no materials
+    triangle_materials: Vec<Option<usize>>,
+}
+
+/// Problems found by `Shape::validate`. Triangles are given by their
+/// index in `Shape::triangles` and edges by their two vertex indices.
+#[derive(Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    /// Triangles with (close to) zero area
+    pub degenerate_triangles: Vec<usize>,
+    /// Triangles referring to vertices that don't exist
+    pub out_of_range_triangles: Vec<usize>,
+    /// Edges shared by more than two triangles
+    pub non_manifold_edges: Vec<(usize, usize)>,
+    /// Edges between two triangles with opposite winding, so one of
+    /// them faces the wrong way
+    pub inconsistent_edges: Vec<(usize, usize)>,
+}
+
+impl ValidationReport {
+    /// Returns true if no problems were found
+    pub fn is_valid(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 impl Shape {
@@ -21,7 +54,13 @@ impl Shape {
         {
             va.push(Vertex::new(*position, *normal, *texcoord));
         }
-        Self { va, triangles }
+        Self {
+            vertex_range: 0..va.len(),
+            va: Rc::new(va),
+            triangles,
+            materials: Vec::new(),
+            triangle_materials: Vec::new(),
+        }
     }
 
     pub fn with_tris(
@@ -50,7 +89,13 @@ impl Shape {
         for (position, normal) in positions.iter().zip(&normals) {
             va.push(Vertex::with_pos_normal(*position, *normal));
         }
-        Self { va, triangles }
+        Self {
+            vertex_range: 0..va.len(),
+            va: Rc::new(va),
+            triangles,
+            materials: Vec::new(),
+            triangle_materials: Vec::new(),
+        }
     }
 
     fn gen_normals(
@@ -76,12 +121,380 @@ impl Shape {
         normals
     }
 
+    /// Splits the polygon with corners at `positions[i]` for every `i`
+    /// in `polygon`, in counter-clockwise order, into triangles facing
+    /// the same way as the polygon. Quads are split along whichever
+    /// diagonal keeps both halves facing that way, preferring the shorter
+    /// one, which suits quads that aren't flat. Larger polygons are split
+    /// by clipping ears, so they may be concave but not self-intersecting.
+    pub fn triangulate_polygon(positions: &[Vec3f], polygon: &[usize]) -> Vec<(usize, usize, usize)> {
+        let n = polygon.len();
+        if n < 3 {
+            return Vec::new();
+        }
+        let p = |i: usize| positions[polygon[i]];
+        // Newell's method, which works for concave and bent polygons too
+        let mut normal = Vec3f::zero();
+        for i in 0..n {
+            let (a, b) = (p(i), p((i + 1) % n));
+            normal += Vec3f::new(
+                (a.y - b.y) * (a.z + b.z),
+                (a.z - b.z) * (a.x + b.x),
+                (a.x - b.x) * (a.y + b.y),
+            );
+        }
+        // Positive if the corners go counter-clockwise around the normal
+        let winding = |i0: usize, i1: usize, i2: usize| {
+            (p(i1) - p(i0)).cross(&(p(i2) - p(i0))).dot(&normal)
+        };
+        let faces_along = |i0: usize, i1: usize, i2: usize| winding(i0, i1, i2) > 0.0;
+        let triangle = |i0: usize, i1: usize, i2: usize| (polygon[i0], polygon[i1], polygon[i2]);
+
+        if n == 4 {
+            let splits = [[(0, 1, 2), (0, 2, 3)], [(1, 2, 3), (1, 3, 0)]];
+            let valid = |split: &[(usize, usize, usize); 2]| split.iter().all(|&(i0, i1, i2)| faces_along(i0, i1, i2));
+            let shorter_first = (p(0) - p(2)).length() <= (p(1) - p(3)).length();
+            let (preferred, other) = if shorter_first { (splits[0], splits[1]) } else { (splits[1], splits[0]) };
+            let split = if valid(&preferred) || !valid(&other) { preferred } else { other };
+            return split.iter().map(|&(i0, i1, i2)| triangle(i0, i1, i2)).collect();
+        }
+
+        let mut remaining: Vec<usize> = (0..n).collect();
+        let mut triangles = Vec::with_capacity(n - 2);
+        while remaining.len() > 3 {
+            let m = remaining.len();
+            // An ear is a corner bending the same way as the polygon,
+            // with no other corner inside or on the edge of the triangle
+            // it cuts off
+            let ear = (0..m).find(|&i| {
+                let (i0, i1, i2) = (remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]);
+                faces_along(i0, i1, i2) && remaining.iter()
+                    .filter(|&&j| j != i0 && j != i1 && j != i2)
+                    .all(|&j| winding(i0, i1, j) < 0.0 || winding(i1, i2, j) < 0.0 || winding(i2, i0, j) < 0.0)
+            });
+            match ear {
+                Some(i) => {
+                    triangles.push(triangle(remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]));
+                    remaining.remove(i);
+                }
+                // Degenerate polygons have no ears, fan what's left
+                None => break,
+            }
+        }
+        for i in 1..remaining.len() - 1 {
+            triangles.push(triangle(remaining[0], remaining[i], remaining[i + 1]));
+        }
+        triangles
+    }
+
+    /// Computes the tangent of every vertex from how the texcoords
+    /// change across its triangles. The tangents are made orthogonal to
+    /// the vertex normals. Triangles without texcoords are ignored.
+    pub fn generate_tangents(&mut self) {
+        let mut tangents = vec![Vec3f::zero(); self.vertex_count()];
+        for &(i0, i1, i2) in &self.triangles {
+            let (v0, v1, v2) = (&self.vertex_slice()[i0], &self.vertex_slice()[i1], &self.vertex_slice()[i2]);
+            let (uv0, uv1, uv2) = match (v0.texcoord, v1.texcoord, v2.texcoord) {
+                (Some(uv0), Some(uv1), Some(uv2)) => (uv0, uv1, uv2),
+                _ => continue,
+            };
+            let e1 = v1.position - v0.position;
+            let e2 = v2.position - v0.position;
+            let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+            let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+            let det = du1 * dv2 - du2 * dv1;
+            if det == 0.0 {
+                continue;
+            }
+            let tangent = (e1.scale(dv2) - e2.scale(dv1)).scale(1.0 / det);
+
+            tangents[i0] += tangent;
+            tangents[i1] += tangent;
+            tangents[i2] += tangent;
+        }
+        for (vertex, tangent) in self.vertex_slice_mut().iter_mut().zip(tangents) {
+            // Remove the part along the normal (Gram-Schmidt)
+            let n = vertex.normal;
+            let t = tangent - n.scale(n.dot(&tangent));
+            vertex.tangent = if t.length() > 0.0 { t.normalize() } else { t };
+        }
+    }
+
+    /// Checks the mesh for problems that make it render incorrectly,
+    /// without changing it
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        // The triangles using each edge, keyed by (lower, higher) index,
+        // along with whether the triangle goes from lower to higher
+        let mut edges: HashMap<(usize, usize), Vec<bool>> = HashMap::new();
+        for (i, &(i0, i1, i2)) in self.triangles.iter().enumerate() {
+            if [i0, i1, i2].iter().any(|&index| index >= self.vertex_count()) {
+                report.out_of_range_triangles.push(i);
+                continue;
+            }
+            let (p0, p1, p2) = (self.vertex_slice()[i0].position, self.vertex_slice()[i1].position, self.vertex_slice()[i2].position);
+            if (p1 - p0).cross(&(p2 - p0)).length() <= f32::EPSILON {
+                report.degenerate_triangles.push(i);
+            }
+            for (a, b) in [(i0, i1), (i1, i2), (i2, i0)] {
+                edges.entry((a.min(b), a.max(b))).or_default().push(a < b);
+            }
+        }
+        for (edge, directions) in edges {
+            if directions.len() > 2 {
+                report.non_manifold_edges.push(edge);
+            } else if directions.len() == 2 && directions[0] == directions[1] {
+                // Neighbouring triangles with the same winding go along
+                // their shared edge in opposite directions
+                report.inconsistent_edges.push(edge);
+            }
+        }
+        report.non_manifold_edges.sort_unstable();
+        report.inconsistent_edges.sort_unstable();
+        report
+    }
+
+    /// Recomputes the vertex normals, averaging only the faces around a
+    /// vertex that are less than `max_angle_deg` degrees apart. Vertices
+    /// on sharper edges are split so that each side keeps its own normal.
+    /// Only faces sharing vertex indices are averaged.
+    pub fn generate_smooth_normals(&mut self, max_angle_deg: f32) {
+        let min_cos = (max_angle_deg * PI / 180.0).cos();
+        let face_normals: Vec<Vec3f> = self.triangles.iter().map(|&(i0, i1, i2)| {
+            let (p0, p1, p2) = (self.vertex_slice()[i0].position, self.vertex_slice()[i1].position, self.vertex_slice()[i2].position);
+            (p1 - p0).cross(&(p2 - p0)).normalize()
+        }).collect();
+
+        // The triangles around every vertex
+        let mut vertex_faces = vec![Vec::new(); self.vertex_count()];
+        for (t, &(i0, i1, i2)) in self.triangles.iter().enumerate() {
+            for i in [i0, i1, i2] {
+                vertex_faces[i].push(t);
+            }
+        }
+
+        // Every corner of every triangle gets a vertex with the average
+        // normal of the faces close enough to its own face, reusing
+        // vertices that already ended up with the same normal
+        let mut va = VertexArray::with_capacity(self.vertex_count());
+        let mut split: Vec<Vec<(Vec3f, usize)>> = vec![Vec::new(); self.vertex_count()];
+        let mut triangles = Vec::with_capacity(self.triangles.len());
+        for (t, &(i0, i1, i2)) in self.triangles.iter().enumerate() {
+            let mut corner = |i: usize| {
+                let mut normal = Vec3f::zero();
+                for &f in &vertex_faces[i] {
+                    if face_normals[f].dot(&face_normals[t]) >= min_cos {
+                        normal += face_normals[f];
+                    }
+                }
+                let normal = normal.normalize();
+                if let Some(&(_, index)) = split[i].iter()
+                    .find(|(n, _)| (*n - normal).length() < 1e-5)
+                {
+                    return index;
+                }
+                let mut vertex = self.vertex_slice()[i];
+                vertex.normal = normal;
+                va.push(vertex);
+                split[i].push((normal, va.len() - 1));
+                va.len() - 1
+            };
+            triangles.push((corner(i0), corner(i1), corner(i2)));
+        }
+        self.vertex_range = 0..va.len();
+        self.va = Rc::new(va);
+        self.triangles = triangles;
+    }
+
+    /// Reverses the winding of every triangle, turning them to face the
+    /// other way when culling
+    pub fn flip_winding(&mut self) {
+        for tri in &mut self.triangles {
+            std::mem::swap(&mut tri.1, &mut tri.2);
+        }
+    }
+
+    /// Negates every vertex normal
+    pub fn flip_normals(&mut self) {
+        for vertex in self.vertex_slice_mut().iter_mut() {
+            vertex.normal = -vertex.normal;
+        }
+    }
+
+    /// Moves all vertices so that the center of the bounding box is at
+    /// the origin
+    pub fn center(&mut self) {
+        let (min, max) = self.bounding_box();
+        let center = (min + max).scale(0.5);
+        for vertex in self.vertex_slice_mut().iter_mut() {
+            vertex.position = vertex.position - center;
+        }
+    }
+
+    /// Scales all vertices uniformly around the origin, so that the
+    /// largest side of the bounding box is `target_size` long
+    pub fn normalize_scale(&mut self, target_size: f32) {
+        let (min, max) = self.bounding_box();
+        let size = (max.x - min.x).max(max.y - min.y).max(max.z - min.z);
+        if size == 0.0 {
+            return;
+        }
+        let factor = target_size / size;
+        for vertex in self.vertex_slice_mut().iter_mut() {
+            vertex.position = vertex.position.scale(factor);
+        }
+    }
+
+    /// Multiplies every vertex's texcoords by (`su`, `sv`). Scaling by
+    /// N tiles a repeating texture N times across the shape.
+    pub fn scale_texcoords(&mut self, su: f32, sv: f32) {
+        for vertex in self.vertex_slice_mut().iter_mut() {
+            if let Some((u, v)) = vertex.texcoord {
+                vertex.texcoord = Some((u * su, v * sv));
+            }
+        }
+    }
+
+    /// Adds (`du`, `dv`) to every vertex's texcoords, for example to
+    /// pick a part of a texture atlas
+    pub fn offset_texcoords(&mut self, du: f32, dv: f32) {
+        for vertex in self.vertex_slice_mut().iter_mut() {
+            if let Some((u, v)) = vertex.texcoord {
+                vertex.texcoord = Some((u + du, v + dv));
+            }
+        }
+    }
+
+    /// Sets the materials of the shape, and which of them every triangle
+    /// uses by index. `triangle_materials` must be as long as the
+    /// number of triangles.
+    pub fn set_materials(&mut self, materials: Vec<Material>, triangle_materials: Vec<Option<usize>>) {
+        assert_eq!(triangle_materials.len(), self.triangles.len());
+        self.materials = materials;
+        self.triangle_materials = triangle_materials;
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    /// Returns the material of the triangle at `triangle_index` in
+    /// `triangles`, if it has one
+    pub fn triangle_material(&self, triangle_index: usize) -> Option<&Material> {
+        let index = (*self.triangle_materials.get(triangle_index)?)?;
+        self.materials.get(index)
+    }
+
+    /// Returns a shape of the triangles in `range`, sharing the vertex
+    /// pool. Its vertices are the ones from the lowest to the highest
+    /// index the triangles use, which in OBJ files is usually exactly
+    /// the vertices of their group.
+    fn subset(&self, range: Range<usize>) -> Shape {
+        let triangles = &self.triangles[range.clone()];
+        let first = triangles.iter().map(|&(i0, i1, i2)| i0.min(i1).min(i2)).min().unwrap_or(0);
+        let last = triangles.iter().map(|&(i0, i1, i2)| i0.max(i1).max(i2) + 1).max().unwrap_or(0);
+        let triangle_materials = if self.triangle_materials.is_empty() {
+            Vec::new()
+        } else {
+            self.triangle_materials[range].to_vec()
+        };
+        let start = self.vertex_range.start;
+        Shape {
+            va: Rc::clone(&self.va),
+            vertex_range: start + first..start + last,
+            triangles: triangles.iter()
+                .map(|&(i0, i1, i2)| (i0 - first, i1 - first, i2 - first))
+                .collect(),
+            materials: self.materials.clone(),
+            triangle_materials,
+        }
+    }
+
+    /// Returns the vertices of this shape in the pool
+    fn vertex_slice(&self) -> &[Vertex] {
+        &self.va.as_slice()[self.vertex_range.clone()]
+    }
+
+    /// Like `vertex_slice`, but first copies the vertices out of the
+    /// pool if other shapes share it
+    fn vertex_slice_mut(&mut self) -> &mut [Vertex] {
+        if Rc::get_mut(&mut self.va).is_none() {
+            self.va = Rc::new(self.vertex_slice().iter().copied().collect());
+            self.vertex_range = 0..self.va.len();
+        }
+        let range = self.vertex_range.clone();
+        &mut Rc::get_mut(&mut self.va).unwrap().as_mut_slice()[range]
+    }
+
     pub fn triangles(&self) -> Iter<(usize, usize, usize)> {
         self.triangles.iter()
     }
 
+    /// Returns an iterator over the positions of the corners of every
+    /// triangle, in local space. Triangles with out-of-range indices
+    /// are skipped.
+    pub fn triangle_positions(&self) -> impl Iterator<Item = (Vec3f, Vec3f, Vec3f)> + '_ {
+        self.triangles.iter().filter_map(move |&(i0, i1, i2)| Some((
+            self.try_get(i0)?.position,
+            self.try_get(i1)?.position,
+            self.try_get(i2)?.position,
+        )))
+    }
+
+    /// Returns the summed area of all triangles
+    pub fn surface_area(&self) -> f32 {
+        self.triangle_positions()
+            .map(|(p0, p1, p2)| (p1 - p0).cross(&(p2 - p0)).length() / 2.0)
+            .sum()
+    }
+
+    /// Returns the volume enclosed by the shape, summed from the signed
+    /// volumes of the tetrahedrons between every triangle and the origin.
+    /// It's negative if the triangles face inwards, i.e. the winding is
+    /// flipped, and only meaningful for closed shapes.
+    pub fn signed_volume(&self) -> f32 {
+        self.triangle_positions()
+            .map(|(p0, p1, p2)| p0.dot(&p1.cross(&p2)) / 6.0)
+            .sum()
+    }
+
+    /// Returns an iterator over all vertices of the shape
+    pub fn vertices(&self) -> Iter<'_, Vertex> {
+        self.vertex_slice().iter()
+    }
+
     pub fn get(&self, index: usize) -> &Vertex {
-        &self.va[index]
+        &self.vertex_slice()[index]
+    }
+
+    /// Returns the vertex at `index`, or None if it's out of range
+    pub fn try_get(&self, index: usize) -> Option<&Vertex> {
+        self.vertex_slice().get(index)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertex_range.len()
+    }
+
+    /// Returns the axis-aligned box enclosing all vertices, as its
+    /// (min, max) corners. An empty shape gives a box at the origin.
+    pub fn bounding_box(&self) -> (Vec3f, Vec3f) {
+        let mut vertices = self.vertices();
+        let first = match vertices.next() {
+            Some(v) => v.position,
+            None => return (Vec3f::zero(), Vec3f::zero()),
+        };
+        vertices.fold((first, first), |(min, max), v| {
+            let p = v.position;
+            (
+                Vec3f::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                Vec3f::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+            )
+        })
     }
 }
 
@@ -140,6 +553,253 @@ pub fn make_uv_sphere(
     Shape::with_texcoords(positions, triangles, texcoords)
 }
 
+/// Creates a surface by revolving `profile` around the Y axis. Every
+/// profile point is a (radius, y) pair, and is swept into `segments`
+/// steps around the axis. Points with radius 0 become poles.
+/// 
+/// Texcoords go from 0 to 1 around the axis (u) and along the profile (v).
+pub fn make_lathe(profile: &[(f32, f32)], segments: u64) -> Shape {
+    revolve(profile, None, segments)
+}
+
+/// Revolves `profile` like `make_lathe`. If `profile_normals` is given,
+/// it holds the (radial, y) normal of every profile point, which is
+/// swept around along with it. Otherwise normals are generated from
+/// the faces.
+fn revolve(profile: &[(f32, f32)], profile_normals: Option<&[(f32, f32)]>, segments: u64) -> Shape {
+
+    assert!(profile.len() >= 2);
+    assert!(segments >= 3);
+
+    // The first column is repeated at the end of every ring so that the
+    // texcoords can wrap around
+    let ring_len = segments as usize + 1;
+    let mut positions = Vec::with_capacity(profile.len() * ring_len);
+    let mut texcoords = Vec::with_capacity(profile.len() * ring_len);
+    for (i, &(radius, y)) in profile.iter().enumerate() {
+        for j in 0..ring_len {
+            let theta = 2.0 * PI * j as f32 / segments as f32;
+            let (sintheta, costheta) = theta.sin_cos();
+            positions.push(Vec3f::new(radius * sintheta, y, radius * costheta));
+            texcoords.push((
+                j as f32 / segments as f32,
+                i as f32 / (profile.len() - 1) as f32,
+            ));
+        }
+    }
+
+    let normals = profile_normals.map(|profile_normals| {
+        assert_eq!(profile_normals.len(), profile.len());
+        let mut normals = Vec::with_capacity(positions.len());
+        for &(radial, y) in profile_normals {
+            for j in 0..ring_len {
+                let theta = 2.0 * PI * j as f32 / segments as f32;
+                let (sintheta, costheta) = theta.sin_cos();
+                normals.push(Vec3f::new(radial * sintheta, y, radial * costheta));
+            }
+        }
+        normals
+    });
+
+    let mut triangles = Vec::with_capacity(2 * (profile.len() - 1) * segments as usize);
+    for i in 0..profile.len() - 1 {
+        for j in 0..segments as usize {
+            let a = i * ring_len + j;
+            let b = a + 1;
+            let c = b + ring_len;
+            let d = a + ring_len;
+            // Skip the triangle that would collapse into a pole
+            if profile[i].0 != 0.0 {
+                triangles.push((a, b, c));
+            }
+            if profile[i + 1].0 != 0.0 {
+                triangles.push((a, c, d));
+            }
+        }
+    }
+    match normals {
+        Some(normals) => Shape::new(positions, normals, texcoords, triangles),
+        None => Shape::with_texcoords(positions, triangles, texcoords),
+    }
+}
+
+/// Creates a capsule around the Y axis: a cylinder `height` tall capped
+/// by two hemispheres of `radius`, centered on the origin. It's split
+/// into `segments` steps around the axis, and the normals are smooth
+/// across where the caps meet the cylinder.
+pub fn make_capsule(radius: f32, height: f32, segments: u64) -> Shape {
+
+    assert!(radius > 0.0);
+    assert!(height >= 0.0);
+
+    let rings = (segments / 4).max(2);
+    let mut profile = Vec::with_capacity(2 * rings as usize + 2);
+    let mut profile_normals = Vec::with_capacity(2 * rings as usize + 2);
+    // From the bottom pole up to the equator of each hemisphere
+    for (center, start) in [(-height / 2.0, -PI / 2.0), (height / 2.0, 0.0)] {
+        for i in 0..=rings {
+            let phi = start + PI / 2.0 * i as f32 / rings as f32;
+            let (sinphi, cosphi) = phi.sin_cos();
+            // Poles need a radius of exactly 0 to be closed
+            let pole = (start < 0.0 && i == 0) || (start >= 0.0 && i == rings);
+            let r = if pole { 0.0 } else { radius * cosphi };
+            profile.push((r, center + radius * sinphi));
+            profile_normals.push((cosphi, sinphi));
+        }
+    }
+    revolve(&profile, Some(&profile_normals), segments)
+}
+
+/// Creates a flat disk of `radius` in the XZ plane facing +Y, as a fan
+/// of `segments` triangles around its center. The texcoords map the
+/// disk onto the circle inscribed in the texture.
+pub fn make_disk(radius: f32, segments: u64) -> Shape {
+
+    assert!(radius > 0.0);
+    assert!(segments >= 3);
+
+    let vertex_count = segments as usize + 1;
+    let mut positions = Vec::with_capacity(vertex_count);
+    let mut texcoords = Vec::with_capacity(vertex_count);
+    positions.push(Vec3f::zero());
+    texcoords.push((0.5, 0.5));
+    for j in 0..segments {
+        let theta = 2.0 * PI * j as f32 / segments as f32;
+        let (sintheta, costheta) = theta.sin_cos();
+        positions.push(Vec3f::new(radius * sintheta, 0.0, radius * costheta));
+        texcoords.push((0.5 + 0.5 * sintheta, 0.5 + 0.5 * costheta));
+    }
+    let normals = vec![Vec3f::new(0.0, 1.0, 0.0); vertex_count];
+
+    let triangles = (1..=segments as usize)
+        .map(|j| (0, j, j % segments as usize + 1))
+        .collect();
+    Shape::new(positions, normals, texcoords, triangles)
+}
+
+/// Extrudes a convex `polygon` in the XY plane along Z, from z = 0 to
+/// z = `depth`. The caps are triangulated as fans, so concave polygons
+/// won't be filled correctly. Every face gets its own vertices so the
+/// edges stay sharp.
+pub fn make_extrusion(polygon: &[(f32, f32)], depth: f32) -> Shape {
+
+    assert!(polygon.len() >= 3);
+    assert!(depth > 0.0);
+
+    // Make the polygon counter-clockwise, so that the caps and walls
+    // below face outwards
+    let signed_area: f32 = (0..polygon.len()).map(|i| {
+        let (x0, y0) = polygon[i];
+        let (x1, y1) = polygon[(i + 1) % polygon.len()];
+        x0 * y1 - x1 * y0
+    }).sum();
+    let mut polygon = polygon.to_vec();
+    if signed_area < 0.0 {
+        polygon.reverse();
+    }
+    let n = polygon.len();
+
+    let mut positions = Vec::with_capacity(4 * n);
+    let mut normals = Vec::with_capacity(4 * n);
+    let mut triangles = Vec::with_capacity(4 * n - 4);
+
+    // Front cap facing +Z and back cap facing -Z
+    for (z, normal) in [(depth, 1.0), (0.0, -1.0)] {
+        let first = positions.len();
+        for &(x, y) in &polygon {
+            positions.push(Vec3f::new(x, y, z));
+            normals.push(Vec3f::new(0.0, 0.0, normal));
+        }
+        for i in 1..n - 1 {
+            if normal > 0.0 {
+                triangles.push((first, first + i, first + i + 1));
+            } else {
+                triangles.push((first, first + i + 1, first + i));
+            }
+        }
+    }
+
+    // Side walls
+    for i in 0..n {
+        let (x0, y0) = polygon[i];
+        let (x1, y1) = polygon[(i + 1) % n];
+        let normal = Vec3f::new(y1 - y0, x0 - x1, 0.0).normalize();
+        let first = positions.len();
+        positions.push(Vec3f::new(x0, y0, 0.0));
+        positions.push(Vec3f::new(x1, y1, 0.0));
+        positions.push(Vec3f::new(x1, y1, depth));
+        positions.push(Vec3f::new(x0, y0, depth));
+        normals.extend([normal; 4]);
+        triangles.push((first, first + 1, first + 2));
+        triangles.push((first, first + 2, first + 3));
+    }
+    Shape::with_normals(positions, normals, triangles)
+}
+
+/// Creates a regular tetrahedron with its corners `radius` from the
+/// origin
+pub fn make_tetrahedron(radius: f32) -> Shape {
+
+    assert!(radius > 0.0);
+
+    let positions = vec![
+        Vec3f::new(1.0, 1.0, 1.0),
+        Vec3f::new(1.0, -1.0, -1.0),
+        Vec3f::new(-1.0, 1.0, -1.0),
+        Vec3f::new(-1.0, -1.0, 1.0),
+    ];
+    let triangles = vec![(0, 1, 2), (0, 1, 3), (0, 2, 3), (1, 2, 3)];
+    make_convex_solid(positions, triangles, radius)
+}
+
+/// Creates a regular octahedron with its corners `radius` from the
+/// origin, on the X, Y and Z axes
+pub fn make_octahedron(radius: f32) -> Shape {
+
+    assert!(radius > 0.0);
+
+    let positions = vec![
+        Vec3f::new(1.0, 0.0, 0.0),
+        Vec3f::new(-1.0, 0.0, 0.0),
+        Vec3f::new(0.0, 1.0, 0.0),
+        Vec3f::new(0.0, -1.0, 0.0),
+        Vec3f::new(0.0, 0.0, 1.0),
+        Vec3f::new(0.0, 0.0, -1.0),
+    ];
+    let mut triangles = Vec::with_capacity(8);
+    for &x in &[0, 1] {
+        for &y in &[2, 3] {
+            for &z in &[4, 5] {
+                triangles.push((x, y, z));
+            }
+        }
+    }
+    make_convex_solid(positions, triangles, radius)
+}
+
+/// Creates a convex solid around the origin from its corners, scaled
+/// to be `radius` from the origin. Triangles are wound to face outwards,
+/// normals point away from the origin and texcoords are mapped as on a
+/// sphere.
+fn make_convex_solid(positions: Vec<Vec3f>, triangles: Vec<(usize, usize, usize)>, radius: f32) -> Shape {
+    let normals: Vec<Vec3f> = positions.iter().map(|p| p.normalize()).collect();
+    let positions: Vec<Vec3f> = normals.iter().map(|n| n.scale(radius)).collect();
+    let texcoords = normals.iter().map(|n| (
+        0.5 + n.x.atan2(n.z) / (2.0 * PI),
+        n.y.clamp(-1.0, 1.0).acos() / PI,
+    )).collect();
+    let triangles = triangles.into_iter().map(|(i0, i1, i2)| {
+        let (p0, p1, p2) = (positions[i0], positions[i1], positions[i2]);
+        let normal = (p1 - p0).cross(&(p2 - p0));
+        if normal.dot(&(p0 + p1 + p2)) < 0.0 {
+            (i0, i2, i1)
+        } else {
+            (i0, i1, i2)
+        }
+    }).collect();
+    Shape::new(positions, normals, texcoords, triangles)
+}
+
 pub fn make_icosphere(radius: f32, refinement_depth: u8) -> Shape {
 
     assert!(radius > 0.0);
@@ -230,24 +890,45 @@ pub fn make_icosphere(radius: f32, refinement_depth: u8) -> Shape {
     Shape::with_tris(positions, triangles)
 }
 
-// pub fn make_cuboid(width: f32, height: f32, length: f32, splits: u64) {
-//     let x0 = -width  / 2.0;
-//     let y0 = -height / 2.0;
-//     let z0 = -length / 2.0;
-//     let point_distance = [
-//             FloatOrd{ 0: width }, 
-//             FloatOrd{ 0: height }, 
-//             FloatOrd{ 0: length }
-//         ].iter().max().unwrap().0 / splits as f32;
-
-//     let x_steps = (width  / point_distance) as usize;
-//     let y_steps = (height / point_distance) as usize;
-//     let z_steps = (length / point_distance) as usize;
+/// Creates a box centered at the origin, `width` along X, `height`
+/// along Y and `length` along Z. Every face has its own four vertices,
+/// so that the edges stay sharp, and covers the whole texture.
+pub fn make_cuboid(width: f32, height: f32, length: f32) -> Shape {
 
-//     for i in 0..x_steps {
+    assert!(width > 0.0);
+    assert!(height > 0.0);
+    assert!(length > 0.0);
 
-//     }
-// }
+    let half = Vec3f::new(width / 2.0, height / 2.0, length / 2.0);
+    // The normal of every face and the direction its texcoord u grows in
+    let faces = [
+        (Vec3f::new(1.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, -1.0)),
+        (Vec3f::new(-1.0, 0.0, 0.0), Vec3f::new(0.0, 0.0, 1.0)),
+        (Vec3f::new(0.0, 1.0, 0.0), Vec3f::new(1.0, 0.0, 0.0)),
+        (Vec3f::new(0.0, -1.0, 0.0), Vec3f::new(1.0, 0.0, 0.0)),
+        (Vec3f::new(0.0, 0.0, 1.0), Vec3f::new(1.0, 0.0, 0.0)),
+        (Vec3f::new(0.0, 0.0, -1.0), Vec3f::new(-1.0, 0.0, 0.0)),
+    ];
+    let mut positions = Vec::with_capacity(24);
+    let mut normals = Vec::with_capacity(24);
+    let mut texcoords = Vec::with_capacity(24);
+    let mut triangles = Vec::with_capacity(12);
+    for (normal, u) in faces.iter() {
+        // u x v is the normal, so the corners go counter-clockwise seen
+        // from outside
+        let v = normal.cross(u);
+        let first = positions.len();
+        for &(a, b) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let p = *normal + u.scale(a) + v.scale(b);
+            positions.push(Vec3f::new(p.x * half.x, p.y * half.y, p.z * half.z));
+            normals.push(*normal);
+            texcoords.push(((a + 1.0) / 2.0, (1.0 - b) / 2.0));
+        }
+        triangles.push((first, first + 1, first + 2));
+        triangles.push((first, first + 2, first + 3));
+    }
+    Shape::new(positions, normals, texcoords, triangles)
+}
 
 pub fn make_quad(width: f32, length: f32, splits: u64) -> Shape {
 
@@ -294,17 +975,77 @@ pub fn make_quad(width: f32, length: f32, splits: u64) -> Shape {
     Shape::with_texcoords(positions, triangles, texcoords)    
 }
 
+/// Creates a square grid of `size`x`size` on the XZ plane split into
+/// `divisions` cells along each side, as gray line segments. The X, Y
+/// and Z axes follow as red, green and blue lines from the origin.
+pub fn make_grid(size: f32, divisions: u64) -> Vec<(Vec3f, Vec3f, Color)> {
+
+    assert!(size > 0.0);
+    assert!(divisions > 0);
+
+    let half = size / 2.0;
+    let step = size / divisions as f32;
+    let mut lines = Vec::with_capacity(2 * (divisions as usize + 1) + 3);
+    for i in 0..=divisions {
+        let offset = -half + i as f32 * step;
+        lines.push((Vec3f::new(offset, 0.0, -half), Vec3f::new(offset, 0.0, half), Color::GRAY));
+        lines.push((Vec3f::new(-half, 0.0, offset), Vec3f::new(half, 0.0, offset), Color::GRAY));
+    }
+
+    let origin = Vec3f::zero();
+    lines.push((origin, Vec3f::new(half, 0.0, 0.0), Color::RED));
+    lines.push((origin, Vec3f::new(0.0, half, 0.0), Color::GREEN));
+    lines.push((origin, Vec3f::new(0.0, 0.0, half), Color::BLUE));
+    lines
+}
+
 pub fn load_from_file<P: AsRef<Path>>(filepath: P) -> Shape {
+    parse_obj(filepath).0
+}
+
+/// Loads an OBJ file as one shape per object or group, named by the
+/// `o` or `g` line starting it. Faces before the first of those lines
+/// form a group with an empty name, as do groups started by an `o` or
+/// `g` line without a name. The shapes share the file's vertex pool.
+pub fn load_groups_from_file<P: AsRef<Path>>(filepath: P) -> Vec<(String, Shape)> {
+    let (shape, groups) = parse_obj(filepath);
+    groups.into_iter()
+        .map(|(name, range)| (name, shape.subset(range)))
+        .collect()
+}
 
+/// Parses an OBJ file into a single shape, along with the name and
+/// range of triangles of every non-empty group in it
+fn parse_obj<P: AsRef<Path>>(filepath: P) -> (Shape, Vec<(String, Range<usize>)>) {
+
+    let mut groups: Vec<(String, Range<usize>)> = Vec::new();
+    let mut group_name = String::new();
+    let mut group_start = 0;
     let mut triangles = Vec::new();
     let mut texcoord_vecs = Vec::new();
     let mut positions = Vec::new();
     let mut texcoords = Vec::new();
+    let mut materials: Vec<Material> = Vec::new();
+    let mut triangle_materials = Vec::new();
+    let mut current_material = None;
 
+    let filepath = filepath.as_ref();
+    let dir = filepath.parent().unwrap_or_else(|| Path::new(""));
     let reader = BufReader::new(File::open(filepath).unwrap());
     for line in reader.lines() {
         let line = line.unwrap();
-        if line.starts_with("v ") {
+        if let Some(mtl_path) = line.strip_prefix("mtllib ") {
+            materials.extend(material::load_mtl(dir.join(mtl_path.trim())));
+        } else if ["o", "g"].contains(&line.trim_end()) || line.starts_with("o ") || line.starts_with("g ") {
+            if triangles.len() > group_start {
+                groups.push((group_name, group_start..triangles.len()));
+            }
+            // A bare `o` or `g` starts a group without a name
+            group_name = line[1..].trim().to_string();
+            group_start = triangles.len();
+        } else if let Some(name) = line.strip_prefix("usemtl ") {
+            current_material = materials.iter().position(|m| m.name == name.trim());
+        } else if line.starts_with("v ") {
             let p = line[2..].split_whitespace().map(|x|
                 x.parse().unwrap()
             ).collect::<Vec<f32>>();
@@ -336,17 +1077,432 @@ pub fn load_from_file<P: AsRef<Path>>(filepath: P) -> Shape {
                     }
                 }).last();
             }).last();
-            let i0 = tri[0];
-            for (i1, i2) in tri[1..].iter().zip(&tri[2..]) {
-                triangles.push((i0, *i1, *i2));
+            for triangle in Shape::triangulate_polygon(&positions, &tri) {
+                triangles.push(triangle);
+                triangle_materials.push(current_material);
             }
         }
     }
     let normals = Shape::gen_normals(&positions, &triangles);
     println!("{} {} {}", positions.len(), normals.len(), texcoords.len());
-    if texcoords.is_empty() {
+    let mut shape = if texcoords.is_empty() {
         Shape::with_normals(positions, normals, triangles)
     } else {
         Shape::new(positions, normals, texcoords, triangles)
+    };
+    if !materials.is_empty() {
+        shape.set_materials(materials, triangle_materials);
+    }
+    if shape.triangles.len() > group_start {
+        groups.push((group_name, group_start..shape.triangles.len()));
+    }
+    (shape, groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quad_tangent_follows_u() {
+        let mut shape = Shape::with_texcoords(
+            vec![
+                Vec3f::new(-1.0, 0.0, -1.0),
+                Vec3f::new(1.0, 0.0, -1.0),
+                Vec3f::new(1.0, 0.0, 1.0),
+                Vec3f::new(-1.0, 0.0, 1.0),
+            ],
+            vec![(0, 2, 1), (0, 3, 2)],
+            vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+        );
+        shape.generate_tangents();
+
+        for i in 0..shape.vertex_count() {
+            let vertex = shape.get(i);
+            assert!((vertex.tangent.x - 1.0).abs() < 1e-5);
+            assert!(vertex.tangent.y.abs() < 1e-5 && vertex.tangent.z.abs() < 1e-5);
+            assert!(vertex.tangent.dot(&vertex.normal).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn vertices_iterates_every_vertex() {
+        let shape = make_icosphere(1.0, 1);
+        assert_eq!(shape.vertices().count(), shape.vertex_count());
+        assert!(shape.vertices().all(|v| (v.position.length() - 1.0).abs() < 1e-4));
+    }
+
+    #[test]
+    fn validate_reports_degenerate_triangle() {
+        assert!(make_icosphere(1.0, 1).validate().is_valid());
+
+        let shape = Shape::with_normals(
+            vec![
+                Vec3f::new(0.0, 0.0, 0.0),
+                Vec3f::new(1.0, 0.0, 0.0),
+                Vec3f::new(0.0, 1.0, 0.0),
+                Vec3f::new(2.0, 0.0, 0.0),
+            ],
+            vec![Vec3f::new(0.0, 0.0, 1.0); 4],
+            vec![(0, 1, 2), (0, 1, 3), (0, 2, 5)],
+        );
+        let report = shape.validate();
+        assert_eq!(report.degenerate_triangles, vec![1]);
+        assert_eq!(report.out_of_range_triangles, vec![2]);
+        assert_eq!(report.inconsistent_edges, vec![(0, 1)]);
+    }
+
+    #[test]
+    fn flipping_twice_restores_shape() {
+        let original = make_icosphere(1.0, 0);
+        let mut shape = original.clone();
+        shape.flip_winding();
+        shape.flip_normals();
+        assert_ne!(shape.triangles, original.triangles);
+        assert!(shape.get(0).normal.dot(&original.get(0).normal) < 0.0);
+
+        shape.flip_winding();
+        shape.flip_normals();
+        assert_eq!(shape.triangles, original.triangles);
+        assert!(shape.get(0).normal.dot(&original.get(0).normal) > 0.0);
+    }
+
+    #[test]
+    fn centered_normalized_cube_spans_target() {
+        // A 4x4x4 cube moved away from the origin to (5, 1, 12)
+        let cuboid = make_cuboid(4.0, 4.0, 4.0);
+        let positions = cuboid.vertices()
+            .map(|v| v.position + Vec3f::new(5.0, 1.0, 12.0))
+            .collect();
+        let mut cube = Shape::with_tris(positions, cuboid.triangles().copied().collect());
+        cube.center();
+        cube.normalize_scale(2.0);
+
+        let (min, max) = cube.bounding_box();
+        for (lo, hi) in [(min.x, max.x), (min.y, max.y), (min.z, max.z)] {
+            assert!((lo + 1.0).abs() < 1e-5 && (hi - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn lathe_of_vertical_line_is_a_cylinder() {
+        let shape = make_lathe(&[(1.0, 0.0), (1.0, 2.0)], 16);
+        assert_eq!(shape.triangle_count(), 2 * 16);
+        assert!(shape.validate().degenerate_triangles.is_empty());
+        for v in shape.vertices() {
+            let radial = Vec3f::new(v.position.x, 0.0, v.position.z);
+            assert!((radial.length() - 1.0).abs() < 1e-5);
+            assert!(v.position.y == 0.0 || v.position.y == 2.0);
+            // Normals point away from the axis
+            assert!(v.normal.dot(&radial) > 0.9);
+        }
+
+        // Poles don't produce degenerate triangles
+        let cone = make_lathe(&[(0.0, 0.0), (1.0, 1.0), (0.0, 2.0)], 8);
+        assert_eq!(cone.triangle_count(), 2 * 8);
+        assert!(cone.validate().degenerate_triangles.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn extruded_square_is_a_box() {
+        // Clockwise, to check that it's turned around
+        let square = [(-1.0, -1.0), (-1.0, 1.0), (1.0, 1.0), (1.0, -1.0)];
+        let shape = make_extrusion(&square, 2.0);
+        assert_eq!(shape.triangle_count(), 12);
+
+        let (min, max) = shape.bounding_box();
+        assert_eq!((min.x, min.y, min.z), (-1.0, -1.0, 0.0));
+        assert_eq!((max.x, max.y, max.z), (1.0, 1.0, 2.0));
+
+        // Every triangle faces away from the center, along its normals
+        let center = Vec3f::new(0.0, 0.0, 1.0);
+        for &(i0, i1, i2) in shape.triangles() {
+            let (v0, v1, v2) = (shape.get(i0), shape.get(i1), shape.get(i2));
+            let face_normal = (v1.position - v0.position).cross(&(v2.position - v0.position)).normalize();
+            assert!(face_normal.dot(&(v0.position - center)) > 0.0);
+            assert!((face_normal.dot(&v0.normal) - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn obj_triangles_get_their_material() {
+        let dir = std::env::temp_dir().join(format!("braillegl-mtl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("colors.mtl"), "\
+newmtl red
+Kd 1.0 0.0 0.0
+newmtl blue
+Kd 0.0 0.0 1.0
+").unwrap();
+        std::fs::write(dir.join("quad.obj"), "\
+mtllib colors.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+usemtl blue
+f 1 2 3
+usemtl red
+f 1 3 4
+").unwrap();
+
+        let shape = load_from_file(dir.join("quad.obj"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(shape.materials().len(), 2);
+        assert_eq!(shape.triangle_material(0).unwrap().diffuse, Color::BLUE);
+        assert_eq!(shape.triangle_material(1).unwrap().diffuse, Color::RED);
+    }
+
+    #[test]
+    fn obj_groups_share_vertex_pool() {
+        let path = std::env::temp_dir().join(format!("braillegl-pool-{}.obj", std::process::id()));
+        std::fs::write(&path, "\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+v 5 0 0
+v 6 0 0
+v 5 1 0
+f 1 2 3
+g
+f 4 5 6
+").unwrap();
+        let mut groups = load_groups_from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[1].0, "");
+        assert!(Rc::ptr_eq(&groups[0].1.va, &groups[1].1.va));
+        assert_eq!(groups[1].1.vertex_count(), 3);
+        assert_eq!(groups[1].1.triangles, vec![(0, 1, 2)]);
+
+        // Changing one group leaves the others as they were
+        groups[1].1.center();
+        assert!(!Rc::ptr_eq(&groups[0].1.va, &groups[1].1.va));
+        assert_eq!(groups[0].1.get(1).position.x, 1.0);
+        assert_eq!(groups[1].1.get(0).position.x, -0.5);
+    }
+
+    #[test]
+    fn obj_textures_have_v_up() {
+        let dir = std::env::temp_dir().join(format!("braillegl-map-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Red on the top row and blue on the bottom one
+        let rgba: [u8; 8] = [255, 0, 0, 255, 0, 0, 255, 255];
+        lodepng::encode32_file(dir.join("stripes.png"), &rgba, 1, 2).unwrap();
+        std::fs::write(dir.join("striped.mtl"), "newmtl striped\nmap_Kd stripes.png\n").unwrap();
+        std::fs::write(dir.join("tri.obj"), "\
+mtllib striped.mtl
+v 0 0 0
+v 1 0 0
+v 0 1 0
+usemtl striped
+f 1 2 3
+").unwrap();
+
+        let shape = load_from_file(dir.join("tri.obj"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let texture = shape.triangle_material(0).unwrap().texture.as_ref().unwrap();
+        assert!(texture.is_flip_v());
+        assert_eq!(texture.sample(0.5, 0.1), Color::BLUE);
+    }
+
+    #[test]
+    fn smooth_normals_keep_cube_edges_sharp() {
+        let positions = (0..8).map(|i| Vec3f::new(
+            if i & 1 == 0 { -1.0 } else { 1.0 },
+            if i & 2 == 0 { -1.0 } else { 1.0 },
+            if i & 4 == 0 { -1.0 } else { 1.0 },
+        )).collect();
+        let triangles = vec![
+            (0, 2, 3), (0, 3, 1), (4, 5, 7), (4, 7, 6),
+            (0, 1, 5), (0, 5, 4), (2, 6, 7), (2, 7, 3),
+            (0, 4, 6), (0, 6, 2), (1, 3, 7), (1, 7, 5),
+        ];
+        let mut cube = Shape::with_tris(positions, triangles);
+        cube.generate_smooth_normals(30.0);
+
+        assert_eq!(cube.vertex_count(), 24);
+        assert!(cube.validate().degenerate_triangles.is_empty());
+        for v in cube.vertices() {
+            let n = v.normal;
+            let largest = n.x.abs().max(n.y.abs()).max(n.z.abs());
+            assert!((largest - 1.0).abs() < 1e-5);
+            // Points out of the face the vertex is on
+            assert!(n.dot(&v.position) > 0.0);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn shape_survives_serde_round_trip() {
+        let shape = make_icosphere(1.0, 1);
+        let json = serde_json::to_string(&shape).unwrap();
+        let restored: Shape = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.triangles, shape.triangles);
+        assert_eq!(restored.vertex_count(), shape.vertex_count());
+        for (a, b) in restored.vertices().zip(shape.vertices()) {
+            assert_eq!((a.position.x, a.position.y, a.position.z), (b.position.x, b.position.y, b.position.z));
+            assert_eq!((a.normal.x, a.normal.y, a.normal.z), (b.normal.x, b.normal.y, b.normal.z));
+        }
+        assert_eq!(serde_json::to_string(&restored).unwrap(), json);
+    }
+
+    #[test]
+    fn grid_ends_with_colored_axes() {
+        let lines = make_grid(4.0, 4);
+        assert_eq!(lines.len(), 2 * 5 + 3);
+        assert!(lines[..10].iter().all(|(_, _, c)| *c == Color::GRAY));
+
+        let axes = &lines[10..];
+        let expected = [
+            (Vec3f::new(2.0, 0.0, 0.0), Color::RED),
+            (Vec3f::new(0.0, 2.0, 0.0), Color::GREEN),
+            (Vec3f::new(0.0, 0.0, 2.0), Color::BLUE),
+        ];
+        for ((start, end, color), (expected_end, expected_color)) in axes.iter().zip(expected.iter()) {
+            assert_eq!(start.length(), 0.0);
+            assert!((*end - *expected_end).length() < 1e-6);
+            assert_eq!(color, expected_color);
+        }
+    }
+
+    #[test]
+    fn quad_triangle_areas_sum_to_quad_area() {
+        let quad = make_quad(1.0, 1.0, 3);
+        let area: f32 = quad.triangle_positions()
+            .map(|(p0, p1, p2)| (p1 - p0).cross(&(p2 - p0)).length() / 2.0)
+            .sum();
+        assert!((area - 1.0).abs() < 1e-5);
+        assert_eq!(quad.triangle_positions().count(), quad.triangle_count());
+    }
+
+    #[test]
+    fn capsule_is_height_plus_caps_tall() {
+        let capsule = make_capsule(0.5, 2.0, 16);
+        let (min, max) = capsule.bounding_box();
+        assert!((max.y - min.y - 3.0).abs() < 1e-5);
+        assert!((max.x - min.x - 1.0).abs() < 1e-5);
+        assert!(capsule.validate().degenerate_triangles.is_empty());
+
+        // Normals point away from the axis segment
+        for v in capsule.vertices() {
+            let closest = Vec3f::new(0.0, v.position.y.clamp(-1.0, 1.0), 0.0);
+            let outwards = (v.position - closest).normalize();
+            assert!(v.normal.dot(&outwards) > 0.99);
+        }
+    }
+
+    #[test]
+    fn disk_is_a_fan_facing_up() {
+        let disk = make_disk(2.0, 12);
+        assert_eq!(disk.triangle_count(), 12);
+        assert_eq!(disk.vertex_count(), 13);
+        assert!(disk.vertices().all(|v| v.normal.y == 1.0));
+        // The winding agrees with the normals
+        for (p0, p1, p2) in disk.triangle_positions() {
+            assert!((p1 - p0).cross(&(p2 - p0)).y > 0.0);
+        }
+        assert!(disk.validate().is_valid());
+    }
+
+    #[test]
+    fn platonic_solids_face_outwards() {
+        let tetrahedron = make_tetrahedron(2.0);
+        assert_eq!(tetrahedron.triangle_count(), 4);
+        assert_eq!(tetrahedron.vertex_count(), 4);
+
+        let octahedron = make_octahedron(2.0);
+        assert_eq!(octahedron.triangle_count(), 8);
+        assert_eq!(octahedron.vertex_count(), 6);
+
+        for solid in &[tetrahedron, octahedron] {
+            assert!(solid.validate().is_valid());
+            assert!(solid.vertices().all(|v| (v.position.length() - 2.0).abs() < 1e-5));
+            for (p0, p1, p2) in solid.triangle_positions() {
+                assert!((p1 - p0).cross(&(p2 - p0)).dot(&(p0 + p1 + p2)) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn scaling_texcoords_by_two_doubles_them() {
+        let quad = make_quad(1.0, 1.0, 1);
+        let mut scaled = quad.clone();
+        scaled.scale_texcoords(2.0, 2.0);
+        for (before, after) in quad.vertices().zip(scaled.vertices()) {
+            let (u, v) = before.texcoord.unwrap();
+            assert_eq!(after.texcoord, Some((u * 2.0, v * 2.0)));
+        }
+
+        scaled.offset_texcoords(0.5, -1.0);
+        let (u, v) = quad.get(1).texcoord.unwrap();
+        assert_eq!(scaled.get(1).texcoord, Some((u * 2.0 + 0.5, v * 2.0 - 1.0)));
+    }
+
+    #[test]
+    fn cuboid_area_and_volume() {
+        let side = 1.5;
+        let cube = make_cuboid(side, side, side);
+        assert_eq!(cube.triangle_count(), 12);
+        assert!((cube.surface_area() - 6.0 * side * side).abs() < 1e-4);
+        assert!((cube.signed_volume() - side * side * side).abs() < 1e-4);
+        assert!(cube.validate().is_valid());
+
+        let (min, max) = make_cuboid(2.0, 4.0, 6.0).bounding_box();
+        assert!((max - min - Vec3f::new(2.0, 4.0, 6.0)).length() < 1e-5);
+
+        // Flipping the winding turns the volume inside out
+        let mut flipped = cube.clone();
+        flipped.flip_winding();
+        assert!((flipped.signed_volume() + side * side * side).abs() < 1e-4);
+    }
+
+    #[test]
+    fn concave_bent_quad_triangulates_without_flips() {
+        let dir = std::env::temp_dir().join(format!("braillegl-quad-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // The last corner is dented inwards and lifted out of the plane,
+        // so fanning from the first corner would fold a triangle over
+        std::fs::write(dir.join("dent.obj"), "\
+v 0 0 0
+v 2 0 0
+v 2 2 0
+v 1.5 0.5 0.3
+f 1 2 3 4
+").unwrap();
+
+        let shape = load_from_file(dir.join("dent.obj"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(shape.triangle_count(), 2);
+        for (p0, p1, p2) in shape.triangle_positions() {
+            assert!((p1 - p0).cross(&(p2 - p0)).z > 0.0);
+        }
+    }
+
+    #[test]
+    fn concave_polygon_is_split_into_ears() {
+        // An L shape, concave at its inner corner
+        let positions = vec![
+            Vec3f::new(0.0, 0.0, 0.0),
+            Vec3f::new(2.0, 0.0, 0.0),
+            Vec3f::new(2.0, 1.0, 0.0),
+            Vec3f::new(1.0, 1.0, 0.0),
+            Vec3f::new(1.0, 2.0, 0.0),
+            Vec3f::new(0.0, 2.0, 0.0),
+        ];
+        let triangles = Shape::triangulate_polygon(&positions, &[0, 1, 2, 3, 4, 5]);
+        assert_eq!(triangles.len(), 4);
+        let area: f32 = triangles.iter().map(|&(i0, i1, i2)| {
+            let (p0, p1, p2) = (positions[i0], positions[i1], positions[i2]);
+            let cross = (p1 - p0).cross(&(p2 - p0));
+            assert!(cross.z > 0.0);
+            cross.z / 2.0
+        }).sum();
+        assert!((area - 3.0).abs() < 1e-5);
+    }
+}
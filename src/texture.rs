@@ -1,14 +1,57 @@
-use std::{ops::{AddAssign, Mul}, path::Path};
+use std::{ops::{Add, AddAssign, Mul}, path::Path};
 
+use crate::math::Vec3f;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Texture {
     data: Vec<Color>,
     width: usize,
     height: usize,
+    flip_v: bool,
+    wrap_mode: WrapMode,
+}
+
+/// The slots an entity can hold a texture in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextureSlot {
+    Diffuse,
+    Normal,
+    Emissive,
+}
+
+/// What texcoords outside [0, 1] sample
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WrapMode {
+    /// The nearest edge pixel, stretching the edge outwards
+    Clamp,
+    /// The texture tiled in every direction
+    Repeat,
+    /// The texture tiled, with every other tile mirrored so that the
+    /// edges between tiles match
+    MirroredRepeat,
+}
+
+impl WrapMode {
+    /// Maps the texcoord `t` into [0, 1]
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            WrapMode::Clamp => t.clamp(0.0, 1.0),
+            WrapMode::Repeat => t - t.floor(),
+            WrapMode::MirroredRepeat => {
+                let t = t.rem_euclid(2.0);
+                if t > 1.0 { 2.0 - t } else { t }
+            }
+        }
+    }
 }
 
 impl Texture {
-    fn new(data: Vec<Color>, width: usize, height: usize) -> Self {
-        Texture { data, width, height }
+    /// Creates a texture from `width * height` colors, stored row by row
+    pub fn new(data: Vec<Color>, width: usize, height: usize) -> Self {
+        Texture { data, width, height, flip_v: false, wrap_mode: WrapMode::Clamp }
     }
 
     pub fn load_from_file<P: AsRef<Path>>(filepath: P) -> Self {
@@ -21,14 +64,111 @@ impl Texture {
         Self::new(data, w, h)
     }
 
+    /// Resamples the texture to `new_w`x`new_h` pixels, blending the
+    /// four nearest pixels of the original for every new one (bilinear)
+    pub fn resize(&self, new_w: usize, new_h: usize) -> Texture {
+        assert!(new_w > 0 && new_h > 0);
+        let pixel = |x: usize, y: usize| self.data[y * self.width + x];
+        let mut data = Vec::with_capacity(new_w * new_h);
+        for y in 0..new_h {
+            for x in 0..new_w {
+                // Map pixel centers onto each other
+                let sx = ((x as f32 + 0.5) * self.width as f32 / new_w as f32 - 0.5)
+                    .clamp(0.0, (self.width - 1) as f32);
+                let sy = ((y as f32 + 0.5) * self.height as f32 / new_h as f32 - 0.5)
+                    .clamp(0.0, (self.height - 1) as f32);
+                let (x0, y0) = (sx as usize, sy as usize);
+                let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+                let (tx, ty) = (sx - x0 as f32, sy - y0 as f32);
+                let top = pixel(x0, y0).lerp(pixel(x1, y0), tx);
+                let bottom = pixel(x0, y1).lerp(pixel(x1, y1), tx);
+                data.push(top.lerp(bottom, ty));
+            }
+        }
+        let mut resized = Texture::new(data, new_w, new_h);
+        resized.flip_v = self.flip_v;
+        resized.wrap_mode = self.wrap_mode;
+        resized
+    }
+
+    /// Sets where `v = 0` is. By default it's the top row, the order
+    /// PNG stores its rows in. Texcoords with the origin in the bottom
+    /// left, as OpenGL-style pipelines export them, need `flip_v`.
+    pub fn set_flip_v(&mut self, flip_v: bool) {
+        self.flip_v = flip_v;
+    }
+
+    pub fn is_flip_v(&self) -> bool {
+        self.flip_v
+    }
+
+    /// Sets what texcoords outside [0, 1] sample. The default, `Clamp`,
+    /// samples the edge.
+    pub fn set_wrap_mode(&mut self, wrap_mode: WrapMode) {
+        self.wrap_mode = wrap_mode;
+    }
+
+    pub fn get_wrap_mode(&self) -> WrapMode {
+        self.wrap_mode
+    }
+
+    /// Returns the color at the texcoords (`u`, `v`), wrapping texcoords
+    /// outside [0, 1], which some OBJ files have, by the texture's wrap
+    /// mode
     pub fn sample(&self, u: f32, v: f32) -> Color {
-        let x = (u * (self.width - 1) as f32) as usize;
-        let y = (v * (self.height - 1) as f32) as usize;
+        self.sample_wrapped(u, v, self.wrap_mode)
+    }
+
+    /// Returns the color at the texcoords (`u`, `v`), wrapping them by
+    /// `wrap_mode` instead of the texture's own
+    pub fn sample_wrapped(&self, u: f32, v: f32, wrap_mode: WrapMode) -> Color {
+        let (u, v) = (wrap_mode.apply(u), wrap_mode.apply(v));
+        let v = if self.flip_v { 1.0 - v } else { v };
+        // Every pixel covers an equal part of [0, 1]
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
         self.data[y * self.width + x]
     }
 }
 
-#[derive(Clone, Copy)]
+/// Six textures on the inside of a cube around the viewer, for
+/// backgrounds such as a sky. The faces are in the order +X, -X, +Y,
+/// -Y, +Z, -Z, oriented like OpenGL cubemaps.
+#[derive(Clone)]
+pub struct Cubemap {
+    pub faces: [Texture; 6],
+}
+
+impl Cubemap {
+    pub fn new(faces: [Texture; 6]) -> Self {
+        Self { faces }
+    }
+
+    /// Returns the color seen when looking in direction `dir`
+    pub fn sample_direction(&self, dir: Vec3f) -> Color {
+        let (ax, ay, az) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+        // The face is picked by the largest component, the other two
+        // give the position on it
+        let (face, s, t, major) = if ax >= ay && ax >= az {
+            if dir.x > 0.0 { (0, -dir.z, -dir.y, ax) } else { (1, dir.z, -dir.y, ax) }
+        } else if ay >= az {
+            if dir.y > 0.0 { (2, dir.x, dir.z, ay) } else { (3, dir.x, -dir.z, ay) }
+        } else if dir.z > 0.0 {
+            (4, dir.x, -dir.y, az)
+        } else {
+            (5, -dir.x, -dir.y, az)
+        };
+        if major == 0.0 {
+            return Color::BLACK;
+        }
+        let u = ((s / major + 1.0) / 2.0).clamp(0.0, 1.0);
+        let v = ((t / major + 1.0) / 2.0).clamp(0.0, 1.0);
+        self.faces[face].sample(u, v)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -51,29 +191,348 @@ impl Color {
     pub const fn is_not_black(&self) -> bool {
         !(self.r == 0 && self.g == 0 && self.b == 0)
     }
+
+    /// Creates a color from its hex code, `0xRRGGBB`. The top byte is
+    /// ignored.
+    pub const fn from_u32(rgb: u32) -> Self {
+        Color::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+    }
+
+    /// Returns the hex code of the color, `0xRRGGBB`
+    pub const fn to_u32(&self) -> u32 {
+        (self.r as u32) << 16 | (self.g as u32) << 8 | self.b as u32
+    }
+
+    /// Returns the escape sequence setting the terminal's text color
+    /// to this color, in truecolor
+    pub fn to_ansi_fg(&self) -> String {
+        format!("\x1b[38;2;{};{};{}m", self.r, self.g, self.b)
+    }
+
+    /// Returns the escape sequence setting the terminal's background
+    /// color to this color, in truecolor
+    pub fn to_ansi_bg(&self) -> String {
+        format!("\x1b[48;2;{};{};{}m", self.r, self.g, self.b)
+    }
+
+    /// Returns the closest entry in the 256-color terminal palette,
+    /// either from the 6x6x6 color cube or the grayscale ramp
+    pub fn to_ansi256(&self) -> u8 {
+        const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+        let nearest_level = |c: u8| (0..6)
+            .min_by_key(|i| (LEVELS[*i] as i32 - c as i32).abs())
+            .unwrap();
+        let (r, g, b) = (nearest_level(self.r), nearest_level(self.g), nearest_level(self.b));
+        let cube = Color::new(LEVELS[r], LEVELS[g], LEVELS[b]);
+        let cube_index = (16 + 36 * r + 6 * g + b) as u8;
+
+        // The grayscale ramp goes from 8 to 238 in steps of 10
+        let average = (self.r as i32 + self.g as i32 + self.b as i32) / 3;
+        let gray_step = ((average - 8 + 5) / 10).clamp(0, 23);
+        let gray_level = (8 + 10 * gray_step) as u8;
+        let gray = Color::new(gray_level, gray_level, gray_level);
+
+        if self.distance_squared(&gray) < self.distance_squared(&cube) {
+            232 + gray_step as u8
+        } else {
+            cube_index
+        }
+    }
+
+    /// Returns the closest of the 16 basic ANSI colors, using the
+    /// default xterm palette
+    pub fn to_ansi16(&self) -> u8 {
+        const PALETTE: [Color; 16] = [
+            Color::new(0, 0, 0), Color::new(205, 0, 0),
+            Color::new(0, 205, 0), Color::new(205, 205, 0),
+            Color::new(0, 0, 238), Color::new(205, 0, 205),
+            Color::new(0, 205, 205), Color::new(229, 229, 229),
+            Color::new(127, 127, 127), Color::new(255, 0, 0),
+            Color::new(0, 255, 0), Color::new(255, 255, 0),
+            Color::new(92, 92, 255), Color::new(255, 0, 255),
+            Color::new(0, 255, 255), Color::new(255, 255, 255),
+        ];
+        (0..16).min_by_key(|i| self.distance_squared(&PALETTE[*i as usize])).unwrap()
+    }
+
+    fn distance_squared(&self, other: &Color) -> i32 {
+        let (dr, dg, db) = (
+            self.r as i32 - other.r as i32,
+            self.g as i32 - other.g as i32,
+            self.b as i32 - other.b as i32,
+        );
+        dr * dr + dg * dg + db * db
+    }
+
+    /// Returns true if every channel differs by at most `tol`, since
+    /// rounding while blending makes exact comparisons brittle
+    pub fn approx_eq(&self, other: &Color, tol: u8) -> bool {
+        self.r.abs_diff(other.r) <= tol
+            && self.g.abs_diff(other.g) <= tol
+            && self.b.abs_diff(other.b) <= tol
+    }
+
+    /// Returns the relative luminance in [0, 1], weighting the channels
+    /// by how bright they appear (Rec. 709)
+    pub fn luminance(&self) -> f32 {
+        (0.2126 * self.r as f32 + 0.7152 * self.g as f32 + 0.0722 * self.b as f32) / 255.0
+    }
+
+    /// Returns the gray with the same luminance
+    pub fn grayscale(&self) -> Color {
+        let l = (self.luminance() * 255.0).round() as u8;
+        Color::new(l, l, l)
+    }
+
+    /// Scales a color that isn't black up until its brightest channel
+    /// is at least `min`, keeping its hue. Terminals show very dark
+    /// colors as black, which hides dim surfaces.
+    pub fn clamp_to_visible(&self, min: u8) -> Color {
+        let max = self.r.max(self.g).max(self.b);
+        if max == 0 || max >= min {
+            return *self;
+        }
+        let factor = min as f32 / max as f32;
+        let scale = |c: u8| (c as f32 * factor).round().min(255.0) as u8;
+        Color::new(scale(self.r), scale(self.g), scale(self.b))
+    }
+
+    /// Linearly interpolates between `self` (`t = 0`) and `other` (`t = 1`)
+    pub fn lerp(&self, other: Color, t: f32) -> Color {
+        let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+        Color::new(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b))
+    }
 }
 
 impl Mul<f32> for Color {
     type Output = Self;
     fn mul(self, rhs: f32) -> Self::Output {
         let rhs = if rhs < 0.0 { 0.0 } else if rhs > 1.0 { 1.0 } else { rhs };
-        let new_color = Self::new(
+        Self::new(
             (self.r as f32 * rhs) as u8, 
             (self.g as f32 * rhs) as u8, 
             (self.b as f32 * rhs) as u8,
-        );
-        if self.is_not_black() && !new_color.is_not_black() {
-            Color::new(1, 1, 1)
-        } else {
-            new_color
-        }
+        )
+    }
+}
+
+impl Mul<Color> for Color {
+    type Output = Self;
+    /// Multiplies the colors channel by channel, as if both were in [0, 1]
+    fn mul(self, rhs: Color) -> Self::Output {
+        let mul = |a: u8, b: u8| (a as u16 * b as u16 / 255) as u8;
+        Self::new(mul(self.r, rhs.r), mul(self.g, rhs.g), mul(self.b, rhs.b))
+    }
+}
+
+impl Add for Color {
+    type Output = Self;
+    /// Adds the colors channel by channel, saturating at 255
+    fn add(self, rhs: Color) -> Self::Output {
+        Self::new(
+            self.r.saturating_add(rhs.r),
+            self.g.saturating_add(rhs.g),
+            self.b.saturating_add(rhs.b),
+        )
     }
 }
 
 impl AddAssign for Color {
+    /// Adds like `+`, saturating at 255
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+/// A color with unbounded floating point channels, where 1.0 is full
+/// intensity. Lighting is summed in `ColorF` and clamped only once
+/// converted back to a `Color`, so that no contribution is lost.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColorF {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+}
+
+impl ColorF {
+
+    pub const BLACK: Self = ColorF::new(0.0, 0.0, 0.0);
+
+    pub const fn new(r: f32, g: f32, b: f32) -> Self {
+        ColorF { r, g, b }
+    }
+
+    /// Clamps every channel to [0, 1] and rounds it to a byte
+    pub fn to_color(&self) -> Color {
+        let to_byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Color::new(to_byte(self.r), to_byte(self.g), to_byte(self.b))
+    }
+}
+
+impl From<Color> for ColorF {
+    fn from(color: Color) -> Self {
+        ColorF::new(
+            color.r as f32 / 255.0,
+            color.g as f32 / 255.0,
+            color.b as f32 / 255.0,
+        )
+    }
+}
+
+impl Mul<f32> for ColorF {
+    type Output = Self;
+    fn mul(self, rhs: f32) -> Self::Output {
+        ColorF::new(self.r * rhs, self.g * rhs, self.b * rhs)
+    }
+}
+
+impl Mul<ColorF> for ColorF {
+    type Output = Self;
+    fn mul(self, rhs: ColorF) -> Self::Output {
+        ColorF::new(self.r * rhs.r, self.g * rhs.g, self.b * rhs.b)
+    }
+}
+
+impl Add for ColorF {
+    type Output = Self;
+    fn add(self, rhs: ColorF) -> Self::Output {
+        ColorF::new(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b)
+    }
+}
+
+impl AddAssign for ColorF {
     fn add_assign(&mut self, rhs: Self) {
-        self.r += rhs.r;
-        self.g += rhs.g;
-        self.b += rhs.b;
+        *self = *self + rhs;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn red_quantizes_to_palette_red() {
+        assert_eq!(Color::RED.to_ansi256(), 196);
+        assert_eq!(Color::RED.to_ansi16(), 9);
+        assert_eq!(Color::new(128, 128, 128).to_ansi256(), 244);
+    }
+
+    #[test]
+    fn blended_color_is_approximately_expected() {
+        let blended = Color::RED.lerp(Color::BLUE, 0.3);
+        assert!(blended.approx_eq(&Color::new(178, 0, 77), 1));
+        assert!(!blended.approx_eq(&Color::new(170, 0, 77), 1));
+        assert_eq!(Color::RED * Color::WHITE, Color::RED);
+    }
+
+    #[test]
+    fn green_is_brighter_than_blue() {
+        assert!(Color::GREEN.luminance() > Color::BLUE.luminance());
+        assert_eq!(Color::WHITE.grayscale(), Color::WHITE);
+        assert_eq!(Color::GREEN.grayscale(), Color::new(182, 182, 182));
+    }
+
+    #[test]
+    fn multiplying_by_zero_gives_black() {
+        assert_eq!(Color::new(10, 10, 10) * 0.0, Color::BLACK);
+        assert_eq!(Color::new(10, 10, 10) * 0.05, Color::BLACK);
+    }
+
+    #[test]
+    fn downscaled_checkerboard_averages() {
+        let checkerboard = |cell: usize| Texture::new((0..16).map(|i| {
+            let (x, y) = (i % 4 / cell, i / 4 / cell);
+            if (x + y) % 2 == 0 { Color::WHITE } else { Color::BLACK }
+        }).collect(), 4, 4);
+
+        // Every 2x2 block has two white and two black pixels
+        let small = checkerboard(1).resize(2, 2);
+        assert_eq!((small.width, small.height), (2, 2));
+        assert!(small.data.iter().all(|c| c.approx_eq(&Color::GRAY, 1)));
+
+        // Every 2x2 block is a single color
+        let small = checkerboard(2).resize(2, 2);
+        assert_eq!(small.data, vec![Color::WHITE, Color::BLACK, Color::BLACK, Color::WHITE]);
+    }
+
+    #[test]
+    fn direction_along_x_samples_x_face_center() {
+        let mut center = vec![Color::BLACK; 9];
+        center[4] = Color::RED;
+        let plain = Texture::new(vec![Color::BLUE; 9], 3, 3);
+        let cubemap = Cubemap::new([
+            Texture::new(center, 3, 3),
+            plain.clone(), plain.clone(), plain.clone(), plain.clone(), plain,
+        ]);
+
+        assert_eq!(cubemap.sample_direction(Vec3f::new(1.0, 0.0, 0.0)), Color::RED);
+        assert_eq!(cubemap.sample_direction(Vec3f::new(1.0, 0.9, 0.0)), Color::BLACK);
+        assert_eq!(cubemap.sample_direction(Vec3f::new(-1.0, 0.0, 0.0)), Color::BLUE);
+    }
+
+    #[test]
+    fn half_lights_sum_to_full_white() {
+        let half_white = ColorF::from(Color::WHITE) * 0.5;
+        let mut light = ColorF::BLACK;
+        light += half_white;
+        light += half_white;
+        assert_eq!(light.to_color(), Color::WHITE);
+
+        // Rounding each light to a Color first loses a step
+        assert_ne!(Color::WHITE * 0.5 + Color::WHITE * 0.5, Color::WHITE);
+
+        // Too much light is clamped, but only at the end
+        assert_eq!((light * 3.0 + ColorF::new(-2.5, 0.0, 0.0)).to_color(), Color::new(128, 255, 255));
+    }
+
+    #[test]
+    fn color_sums_saturate() {
+        let mut color = Color::new(200, 100, 0);
+        color += Color::new(100, 100, 10);
+        assert_eq!(color, Color::new(200, 100, 0) + Color::new(100, 100, 10));
+        assert_eq!(color, Color::new(255, 200, 10));
+    }
+
+    #[test]
+    fn flip_v_mirrors_rows() {
+        let mut texture = Texture::new(vec![Color::RED, Color::RED, Color::BLUE, Color::BLUE], 2, 2);
+        assert_eq!(texture.sample(0.0, 0.0), Color::RED);
+        assert_eq!(texture.sample(1.0, 1.0), Color::BLUE);
+
+        texture.set_flip_v(true);
+        assert_eq!(texture.sample(0.0, 0.0), Color::BLUE);
+        assert_eq!(texture.sample(1.0, 1.0), Color::RED);
+        assert!(texture.resize(4, 4).is_flip_v());
+    }
+
+    #[test]
+    fn dark_colors_clamp_up_but_black_stays() {
+        assert_eq!(Color::new(2, 0, 0).clamp_to_visible(30), Color::new(30, 0, 0));
+        assert_eq!(Color::new(2, 1, 0).clamp_to_visible(30), Color::new(30, 15, 0));
+        assert_eq!(Color::BLACK.clamp_to_visible(30), Color::BLACK);
+        assert_eq!(Color::GRAY.clamp_to_visible(30), Color::GRAY);
+    }
+
+    #[test]
+    fn wrap_modes_map_texcoords_outside_unit_range() {
+        let mut texture = Texture::new(vec![Color::RED, Color::GREEN, Color::BLUE, Color::WHITE], 4, 1);
+        assert_eq!(texture.sample(1.1, 0.0), Color::WHITE);
+        texture.set_wrap_mode(WrapMode::Repeat);
+        assert_eq!(texture.sample(1.1, 0.0), Color::RED);
+        assert_eq!(texture.sample(-0.1, 0.0), Color::WHITE);
+        assert_eq!(texture.sample_wrapped(1.1, 0.0, WrapMode::MirroredRepeat), Color::WHITE);
+        assert_eq!(texture.sample_wrapped(1.6, 0.0, WrapMode::MirroredRepeat), Color::GREEN);
+    }
+
+    #[test]
+    fn hex_codes_round_trip() {
+        assert_eq!(Color::from_u32(0x123456).to_u32(), 0x123456);
+        assert_eq!(Color::from_u32(0xff8000), Color::new(255, 128, 0));
+
+        let fg = Color::new(12, 34, 56).to_ansi_fg();
+        assert!(fg.contains("12;34;56"));
+        assert!(fg.starts_with("\x1b[38;2;"));
+        assert!(Color::RED.to_ansi_bg().starts_with("\x1b[48;2;255;0;0"));
+    }
+}
@@ -1,13 +1,17 @@
-use std::slice::{Iter, IterMut};
+use std::{iter::FromIterator, slice::{Iter, IterMut}};
 use std::ops::{Index, IndexMut};
 
 use crate::math::Vec3f;
 
 #[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vertex {
     pub position: Vec3f,
     pub normal: Vec3f,
     pub texcoord: Option<(f32, f32)>,
+    /// Points in the direction of increasing u in texture space, zero
+    /// until `Shape::generate_tangents` has been called
+    pub tangent: Vec3f,
 }
 
 impl Vertex {
@@ -16,6 +20,7 @@ impl Vertex {
             position,
             normal,
             texcoord: Some(texcoord),
+            tangent: Vec3f::zero(),
         }
     }
 
@@ -24,10 +29,19 @@ impl Vertex {
             position,
             normal,
             texcoord: None,
+            tangent: Vec3f::zero(),
         }
     }
+
+    /// Returns the direction of increasing v in texture space, completing
+    /// the tangent space basis together with the normal and tangent
+    pub fn bitangent(&self) -> Vec3f {
+        self.normal.cross(&self.tangent)
+    }
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VertexArray {
     vertices: Vec<Vertex>,
 }
@@ -53,11 +67,44 @@ impl VertexArray {
         self.vertices.push(vertex);
     }
 
+    /// Removes all vertices, keeping the allocated capacity
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// Reserves capacity for at least `additional` more vertices
+    /// 
+    /// # Arguments
+    /// `additional` - the amount of vertices that can be added before a reallocation is needed
+    pub fn reserve(&mut self, additional: usize) {
+        self.vertices.reserve(additional);
+    }
+
     /// Returns the length
     pub fn len(&self) -> usize {
         self.vertices.len()
     }
 
+    /// Returns true if there are no vertices
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// Returns the vertex at `index`, or None if it's out of range
+    pub fn get(&self, index: usize) -> Option<&Vertex> {
+        self.vertices.get(index)
+    }
+
+    /// Returns the vertices as a slice
+    pub fn as_slice(&self) -> &[Vertex] {
+        &self.vertices
+    }
+
+    /// Returns the vertices as a mutable slice
+    pub fn as_mut_slice(&mut self) -> &mut [Vertex] {
+        &mut self.vertices
+    }
+
     /// Returns an iterator over the vertices
     pub fn vertices(&self) -> Iter<Vertex> {
         self.vertices.iter()
@@ -80,4 +127,57 @@ impl IndexMut<usize> for VertexArray {
     fn index_mut(&mut self, index: usize) -> &mut Vertex {
         &mut self.vertices[index]
     }
-}
\ No newline at end of file
+}
+
+impl FromIterator<Vertex> for VertexArray {
+    fn from_iter<I: IntoIterator<Item = Vertex>>(iter: I) -> Self {
+        Self { vertices: iter.into_iter().collect() }
+    }
+}
+
+impl Extend<Vertex> for VertexArray {
+    fn extend<I: IntoIterator<Item = Vertex>>(&mut self, iter: I) {
+        self.vertices.extend(iter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex_at(x: f32) -> Vertex {
+        Vertex::with_pos_normal(Vec3f::new(x, 0.0, 0.0), Vec3f::new(0.0, 1.0, 0.0))
+    }
+
+    #[test]
+    fn clear_then_extend() {
+        let mut va = VertexArray::new();
+        va.push(vertex_at(0.0));
+        va.clear();
+        assert_eq!(va.len(), 0);
+
+        va.reserve(3);
+        va.extend((1..4).map(|i| vertex_at(i as f32)));
+        assert_eq!(va.len(), 3);
+        assert_eq!(va[2].position.x, 3.0);
+    }
+
+    #[test]
+    fn collect_vertices() {
+        let va: VertexArray = (0..3).map(|i| vertex_at(i as f32)).collect();
+        assert_eq!(va.len(), 3);
+        for i in 0..3 {
+            assert_eq!(va[i].position.x, i as f32);
+        }
+    }
+
+    #[test]
+    fn get_out_of_range_is_none() {
+        let mut va = VertexArray::new();
+        assert!(va.is_empty());
+        va.push(vertex_at(1.0));
+        assert!(!va.is_empty());
+        assert_eq!(va.get(0).map(|v| v.position.x), Some(1.0));
+        assert!(va.get(1).is_none());
+    }
+}
@@ -1,105 +1,411 @@
 use std::{io::{self, Write}, time::{Duration, Instant}};
 
-use device_query::{DeviceQuery, DeviceState};
-use termion::{clear, color::White, cursor, input::MouseTerminal, raw::IntoRawMode};
+use crate::{backend::{Backend, DefaultBackend}, canvas::{Camera, Canvas, RenderMode}, entity::Entity, input::{Input, InputState, Key}, scene::Scene, shapes, texture::Color};
 
-use crate::{canvas::Canvas, entity::Entity, shapes};
+pub mod timestep;
 
-pub struct Window { }
+pub use timestep::Timestep;
+
+/// The settings a `Window` starts with
+pub struct WindowConfig {
+    /// The OBJ file to load
+    pub model_path: String,
+    /// The initial scale of the loaded entity
+    pub scale: f32,
+    /// How far in front of the camera the entity is placed
+    pub distance: f32,
+    /// The field of view of the projection, in degrees
+    pub fov: f32,
+    /// The width of a terminal cell divided by its height
+    pub cell_aspect: f32,
+    /// The number of frames per second the window tries to draw
+    pub target_fps: u32,
+    /// The number of fixed steps per second `Window::run_with_update`
+    /// updates the scene in
+    pub update_rate: u32,
+    pub camera: Camera,
+    /// The background color, or `None` to keep the terminal's own
+    pub clear_color: Option<Color>,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            model_path: String::from("res/objects/teapot.obj"),
+            scale: 1.0,
+            distance: 6.0,
+            fov: 90.0,
+            cell_aspect: 0.5,
+            target_fps: 60,
+            update_rate: 60,
+            camera: Camera::default(),
+            clear_color: None,
+        }
+    }
+}
+
+pub struct Window {
+    config: WindowConfig,
+}
 
 impl Window {
 
     pub fn default() -> Self {
-        Self {  }
+        Self::new(WindowConfig::default())
+    }
+
+    /// Creates a window that starts out as described by `config`
+    pub fn new(config: WindowConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn get_config(&self) -> &WindowConfig {
+        &self.config
     }
 
     pub fn run(&self) {
-        // Set terminal to raw mode
-        let mut _stdout = MouseTerminal::from(
-            io::stdout().into_raw_mode().unwrap()
-        );
-        print!("{}{}", cursor::Hide, clear::All);
+        let config = &self.config;
 
-        // Init canvas
-        let mut canvas = Canvas::new();
-        
         // Load geometry
         let mut entity = Entity::with_geometry(
             // shapes::make_uv_sphere(5.0, 100, 100)
             // shapes::make_icosphere(4.0, 3)
             // shapes::make_quad(10.0, 20.0, 60)
-            shapes::load_from_file("res/objects/teapot.obj")
+            shapes::load_from_file(&config.model_path)
         );
-        entity.set_translation(0.0, 0.0, -30.0);
-        entity.load_texture("res/textures/f.png");
-        let mut ent_rot: f32 = 0.0;
-        let mut ent_yaw: f32 = 0.0;
+        // Place the entity in front of the camera
+        let camera = config.camera;
+        let start = camera.position + camera.forward().scale(config.distance);
+        entity.set_translation(start.x, start.y, start.z);
+        entity.set_scale(config.scale);
+        entity.load_texture("res/textures/f.png", None);
+        // Radians per second the entity turns while a key is held
+        let turn_speed = 0.6;
+        // Units and radians per second the camera moves and turns
+        let fly_speed = 3.0;
+        let look_speed = 1.2;
+        let mut camera = config.camera;
 
         // Define user constants
-        let preferred_fps = 60;
+        let mut render_mode = RenderMode::Filled;
+        let mut show_hud = true;
 
         // Getting loop variables initialized
-        let d_state = DeviceState::new();
-        // let mut prev_mouse = d_state.get_mouse();
-        let millis_between_frames = 1000 / (preferred_fps + 2);
         let mut tick: u64 = 0;
+        let mut frame_ms: f32 = 0.0;
         let time = Instant::now();
 
-        'main: loop {
-            // Update time
+        self.run_loop(|canvas, state, dt| {
             let t = time.elapsed().as_secs_f32();
+            let frame_start = Instant::now();
 
-            // Get input state
-            // let mouse = d_state.get_mouse();
-            let keys = d_state.get_keys();
-            
             // Handle events
-            for k in &keys {
-                use device_query::Keycode::*;
+            for k in state.keys() {
+                // Toggles should only fire once per key press, not every
+                // frame the key is held down
+                if state.just_pressed(*k) {
+                    handle_toggle(k, &mut render_mode, &mut show_hud);
+                }
                 match k {
-                    Escape => break 'main,
-                    W => entity.translate(0.0, 0.15, 0.0),
-                    S => entity.translate(0.0, -0.15, 0.0),
-                    A => entity.translate(0.15, 0.0, 0.0),
-                    D => entity.translate(-0.15, 0.0, 0.0),
-                    Q => entity.translate(0.0, 0.0, -0.15),
-                    E => entity.translate(0.0, 0.0, 0.15),
-                    R => ent_rot += 0.01,
-                    T => ent_yaw += 0.01,
-                    G => ent_yaw -= 0.01,
-                    _ => {},
+                    Key::Char('r') => entity.rotate_yaw(turn_speed * dt),
+                    Key::Char('t') => entity.rotate_pitch(-turn_speed * dt),
+                    Key::Char('g') => entity.rotate_pitch(turn_speed * dt),
+                    // Zoom by narrowing or widening the field of view
+                    Key::Char('+') | Key::Char('=') => canvas.set_fov((canvas.get_fov() - 1.0).max(10.0)),
+                    Key::Char('-') => canvas.set_fov((canvas.get_fov() + 1.0).min(170.0)),
+                    _ => fly_camera(&mut camera, k, fly_speed * dt, look_speed * dt),
                 }
             }
+            canvas.set_camera(camera);
             // if mouse.button_pressed[1] && prev_mouse.button_pressed[1] {
             //     let (mx, my) = canvas.pix2cell(mouse.coords);
             //     let (pmx, pmy) = canvas.pix2cell(prev_mouse.coords);
             //     canvas.draw_line(pmx, pmy, mx, my);
             // }
 
-            // Update positions
-            entity.set_direction(ent_rot.sin(), ent_yaw.sin(), ent_rot.cos());
-
             // Render
+            canvas.set_render_mode(render_mode);
             canvas.clear();
             canvas.draw_entity(&entity);
-            print!("{}{}{}fps={}", canvas.to_s(), cursor::Goto(1, 1), White.fg_str(), (tick as f32 / t) as u64);
-            io::stdout().flush().unwrap();
+            if show_hud {
+                let stats = canvas.last_frame_stats();
+                let hud = format!("fps={} ms={:.1} tris={} culled={} verts={}",
+                    (tick as f32 / t) as u64,
+                    frame_ms,
+                    stats.triangles_submitted,
+                    stats.triangles_culled,
+                    entity.shape.vertex_count(),
+                );
+                canvas.draw_text(0, 0, &hud, Color::WHITE);
+            }
 
-            // Save states for next frame
-            // prev_mouse = mouse;
             tick += 1;
-            let frame_time = ((time.elapsed().as_secs_f32() - t) * 1000.0) as u64;
-            let frame_time = if frame_time >= millis_between_frames {
-                millis_between_frames
-            } else {
-                frame_time
-            };
-            std::thread::sleep(Duration::from_millis(millis_between_frames - frame_time));
+            frame_ms = frame_start.elapsed().as_secs_f32() * 1000.0;
+        });
+    }
+
+    /// Runs the window with game logic in `update` instead of the
+    /// built-in viewer. `update` gets the scene, the input state and
+    /// the seconds to advance by, which is always `1 / update_rate` of
+    /// the config. It's called as often as needed to keep up with the
+    /// time that has passed, zero or more times every frame, after which
+    /// the window draws the scene as seen by the configured camera. The
+    /// scene starts out empty, entities can be added on the first frame.
+    /// Esc and Ctrl-C close the window.
+    pub fn run_with_update(&self, mut update: impl FnMut(&mut Scene, &InputState, f32)) {
+        let mut scene = Scene::new();
+        let mut timestep = Timestep::new(1.0 / self.config.update_rate.max(1) as f32);
+        self.run_loop(|canvas, state, dt| {
+            update_frame(canvas, &mut scene, state, &mut timestep, dt, &mut update);
+        });
+    }
+
+    /// Sets up the terminal and a canvas as configured, then every frame
+    /// reads the input, calls `step` with the canvas, the input state and
+    /// the seconds since the last frame, and prints the canvas. Returns
+    /// once Esc or Ctrl-C is pressed.
+    fn run_loop(&self, mut step: impl FnMut(&mut Canvas, &InputState, f32)) {
+        // Set terminal to raw mode. The terminal is restored when these
+        // are dropped, in reverse order, even if drawing panics.
+        let _raw_mode = DefaultBackend::raw_mode();
+        let _restore = TerminalGuard::new(io::stdout());
+        let mut setup = String::new();
+        DefaultBackend::hide_cursor(&mut setup);
+        DefaultBackend::clear(&mut setup);
+        print!("{}", setup);
+
+        // Init canvas
+        let config = &self.config;
+        let mut canvas = Canvas::with_fov(config.fov);
+        canvas.set_camera(config.camera);
+        canvas.set_clear_color(config.clear_color);
+        canvas.set_cell_aspect(config.cell_aspect);
+
+        let mut input = Input::new();
+        let mut state = InputState::default();
+        let mut prev_terminal_size = DefaultBackend::terminal_size();
+        let frame_duration = Duration::from_secs_f64(1.0 / config.target_fps.max(1) as f64);
+        let mut prev_frame_start = Instant::now();
+
+        loop {
+            // Update time
+            let frame_start = Instant::now();
+            let dt = frame_start.duration_since(prev_frame_start).as_secs_f32();
+            prev_frame_start = frame_start;
+
+            // Get input state
+            state = input.poll_state(&state);
+            if state.keys().iter().any(is_exit_key) {
+                break;
+            }
+
+            // Follow the size of the terminal
+            let terminal_size = DefaultBackend::terminal_size();
+            if terminal_size != prev_terminal_size {
+                canvas.resize(terminal_size.0 as usize * 2, terminal_size.1 as usize * 4);
+                prev_terminal_size = terminal_size;
+            }
+
+            step(&mut canvas, &state, dt);
+            let mut stdout = io::stdout();
+            canvas.write_to(&mut stdout).unwrap();
+            stdout.flush().unwrap();
+
+            std::thread::sleep(frame_sleep(frame_duration, frame_start.elapsed()));
         }
+    }
+}
 
-        // Reset text color and cursor visibility
-        print!("{}{}{}{}", White.fg_str(), clear::All, cursor::Goto(1, 1), cursor::Show);
-        io::stdout().flush().unwrap();
-        drop(_stdout);
+/// Resets the text color, clears the screen and shows the cursor again
+/// when dropped, so that the terminal is usable after the window
+/// closes however that happens
+struct TerminalGuard<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TerminalGuard<W> {
+    fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Drop for TerminalGuard<W> {
+    fn drop(&mut self) {
+        let mut reset = String::new();
+        DefaultBackend::reset_colors(&mut reset);
+        DefaultBackend::clear(&mut reset);
+        DefaultBackend::goto(&mut reset, 1, 1);
+        DefaultBackend::show_cursor(&mut reset);
+        // Errors can't be reported from here, and there's nothing left
+        // to do about them anyway
+        let _ = self.out.write_all(reset.as_bytes());
+        let _ = self.out.flush();
+    }
+}
+
+/// Returns true for the keys that close the window. Raw mode turns
+/// Ctrl-C into a key press instead of an interrupt.
+fn is_exit_key(key: &Key) -> bool {
+    matches!(key, Key::Esc | Key::Ctrl('c'))
+}
+
+/// Runs one frame of `Window::run_with_update`: updates the scene in
+/// the fixed steps due after `elapsed` seconds and draws it onto a
+/// cleared canvas
+fn update_frame<F>(
+    canvas: &mut Canvas,
+    scene: &mut Scene,
+    state: &InputState,
+    timestep: &mut Timestep,
+    elapsed: f32,
+    update: &mut F,
+)
+where
+    F: FnMut(&mut Scene, &InputState, f32),
+{
+    for _ in 0..timestep.advance(elapsed) {
+        update(scene, state, timestep.get_dt());
+    }
+    canvas.clear();
+    canvas.draw_scene(scene);
+}
+
+/// Returns how long to sleep after a frame that took `elapsed` so that
+/// frames are `frame_duration` apart, or zero if the frame ran over
+fn frame_sleep(frame_duration: Duration, elapsed: Duration) -> Duration {
+    frame_duration.saturating_sub(elapsed)
+}
+
+/// Moves the camera for keys held down: WASD moves it along its own
+/// axes, Q/E down and up, the arrow keys turn it and Z/C roll it.
+/// `distance` and `angle` are how far to move and turn.
+fn fly_camera(camera: &mut Camera, key: &Key, distance: f32, angle: f32) {
+    match key {
+        Key::Char('w') => camera.move_relative(distance, 0.0, 0.0),
+        Key::Char('s') => camera.move_relative(-distance, 0.0, 0.0),
+        Key::Char('a') => camera.move_relative(0.0, -distance, 0.0),
+        Key::Char('d') => camera.move_relative(0.0, distance, 0.0),
+        Key::Char('q') => camera.move_relative(0.0, 0.0, -distance),
+        Key::Char('e') => camera.move_relative(0.0, 0.0, distance),
+        Key::Left => camera.rotate(angle, 0.0),
+        Key::Right => camera.rotate(-angle, 0.0),
+        Key::Up => camera.rotate(0.0, angle),
+        Key::Down => camera.rotate(0.0, -angle),
+        Key::Char('z') => camera.rotate_roll(angle),
+        Key::Char('c') => camera.rotate_roll(-angle),
+        _ => {},
+    }
+}
+
+/// Handles the keys that toggle viewer settings rather than move
+/// the entity. `M` cycles the render mode and `H` shows/hides the HUD.
+fn handle_toggle(key: &Key, render_mode: &mut RenderMode, show_hud: &mut bool) {
+    match key {
+        Key::Char('m') => *render_mode = render_mode.next(),
+        Key::Char('h') => *show_hud = !*show_hud,
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::math::Vec3f;
+
+    #[test]
+    fn toggle_keys_flip_mode_and_hud() {
+        let mut render_mode = RenderMode::Filled;
+        let mut show_hud = true;
+
+        handle_toggle(&Key::Char('m'), &mut render_mode, &mut show_hud);
+        assert_eq!(render_mode, RenderMode::Wireframe);
+        assert!(show_hud);
+
+        handle_toggle(&Key::Char('h'), &mut render_mode, &mut show_hud);
+        assert_eq!(render_mode, RenderMode::Wireframe);
+        assert!(!show_hud);
+
+        handle_toggle(&Key::Char('m'), &mut render_mode, &mut show_hud);
+        handle_toggle(&Key::Char('m'), &mut render_mode, &mut show_hud);
+        assert_eq!(render_mode, RenderMode::Filled);
+    }
+
+    #[test]
+    fn forward_moves_along_camera_direction() {
+        let mut camera = Camera::from_yaw_pitch(Vec3f::zero(), PI / 2.0, 0.0);
+        fly_camera(&mut camera, &Key::Char('w'), 2.0, 0.1);
+        assert!((camera.position - Vec3f::new(2.0, 0.0, 0.0)).length() < 1e-5);
+
+        // Strafing right while facing +X moves towards +Z
+        fly_camera(&mut camera, &Key::Char('d'), 1.0, 0.1);
+        assert!((camera.position - Vec3f::new(2.0, 0.0, 1.0)).length() < 1e-5);
+
+        // Turning left while facing +X turns towards -Z
+        fly_camera(&mut camera, &Key::Left, 1.0, 0.1);
+        assert!(camera.forward().z < 0.0);
+    }
+
+    #[test]
+    fn ctrl_c_closes_and_restores_terminal() {
+        assert!(is_exit_key(&Key::Ctrl('c')));
+        assert!(is_exit_key(&Key::Esc));
+        assert!(!is_exit_key(&Key::Char('c')));
+
+        let mut out = Vec::new();
+        drop(TerminalGuard::new(&mut out));
+        let mut show_cursor = String::new();
+        DefaultBackend::show_cursor(&mut show_cursor);
+        assert!(String::from_utf8(out).unwrap().ends_with(&show_cursor));
+    }
+
+    #[test]
+    fn window_keeps_config() {
+        let window = Window::new(WindowConfig {
+            model_path: String::from("model.obj"),
+            target_fps: 30,
+            fov: 60.0,
+            clear_color: Some(Color::BLUE),
+            ..WindowConfig::default()
+        });
+        let config = window.get_config();
+        assert_eq!(config.model_path, "model.obj");
+        assert_eq!(config.target_fps, 30);
+        assert_eq!(config.fov, 60.0);
+        assert_eq!(config.clear_color.map(|c| c.b), Some(255));
+        assert_eq!(config.distance, WindowConfig::default().distance);
+    }
+
+    #[test]
+    fn frame_sleep_fills_the_remainder() {
+        let frame_duration = Duration::from_millis(16);
+        assert_eq!(frame_sleep(frame_duration, Duration::from_millis(10)), Duration::from_millis(6));
+        assert_eq!(frame_sleep(frame_duration, Duration::from_millis(40)), Duration::ZERO);
+    }
+
+    #[test]
+    fn update_runs_once_per_frame() {
+        let mut canvas = Canvas::with_size(16, 16);
+        let mut scene = Scene::new();
+        let state = InputState::new(vec![Key::Char('w')], None);
+        let mut times = Vec::new();
+        let mut time = 0.0;
+        let mut update = |scene: &mut Scene, state: &InputState, dt: f32| {
+            time += dt;
+            times.push(time);
+            assert!(state.is_key_down(Key::Char('w')));
+            if scene.is_empty() {
+                scene.add(Entity::with_geometry(shapes::make_icosphere(1.0, 0)));
+            }
+        };
+        let mut timestep = Timestep::new(0.25);
+        for _ in 0..3 {
+            update_frame(&mut canvas, &mut scene, &state, &mut timestep, 0.25, &mut update);
+        }
+        // Slow frames catch up in several fixed steps
+        update_frame(&mut canvas, &mut scene, &state, &mut timestep, 0.5, &mut update);
+        assert_eq!(times, vec![0.25, 0.5, 0.75, 1.0, 1.25]);
+        assert_eq!(scene.len(), 1);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,77 @@
+/// Turns the varying time between frames into a whole number of fixed
+/// steps, so that updates behave the same however fast frames are drawn.
+/// Time that doesn't make up a whole step is carried over to the next
+/// frame.
+pub struct Timestep {
+    dt: f32,
+    accumulator: f32,
+    max_steps: u32,
+}
+
+impl Timestep {
+    /// Steps smaller than this fraction of `dt` short of a whole step
+    /// still count, so that rounding doesn't drop steps
+    const TOLERANCE: f32 = 1e-4;
+
+    /// Creates a timestep of `dt` seconds
+    pub fn new(dt: f32) -> Self {
+        assert!(dt > 0.0, "timestep must be positive");
+        Self { dt, accumulator: 0.0, max_steps: 8 }
+    }
+
+    /// Adds `elapsed` seconds and returns how many steps of `dt` are now
+    /// due. At most `max_steps` are returned, dropping the time of the
+    /// rest, so that a long stall doesn't make every following frame
+    /// catch up.
+    pub fn advance(&mut self, elapsed: f32) -> u32 {
+        self.accumulator += elapsed.max(0.0);
+        let mut steps = 0;
+        while self.accumulator >= self.dt * (1.0 - Self::TOLERANCE) {
+            self.accumulator = (self.accumulator - self.dt).max(0.0);
+            steps += 1;
+            if steps == self.max_steps {
+                self.accumulator = 0.0;
+                break;
+            }
+        }
+        steps
+    }
+
+    /// Returns how far the time carried over is into the next step,
+    /// from 0 to 1. `Window::run_with_update` draws the state after the
+    /// last step as is, but a loop of its own can use this to interpolate
+    /// between the last two states when drawing.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.dt
+    }
+
+    pub fn get_dt(&self) -> f32 {
+        self.dt
+    }
+
+    /// Sets the most steps `advance` returns at once
+    pub fn set_max_steps(&mut self, max_steps: u32) {
+        self.max_steps = max_steps.max(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fifty_milliseconds_is_three_sixtieths() {
+        let mut timestep = Timestep::new(1.0 / 60.0);
+        assert_eq!(timestep.advance(0.05), 3);
+        assert!(timestep.alpha() < 1e-3);
+
+        // Leftover time adds up to a step over several frames
+        assert_eq!(timestep.advance(0.01), 0);
+        assert_eq!(timestep.advance(0.01), 1);
+        assert!((timestep.alpha() - 0.2).abs() < 1e-3);
+
+        timestep.set_max_steps(4);
+        assert_eq!(timestep.advance(10.0), 4);
+        assert_eq!(timestep.alpha(), 0.0);
+    }
+}